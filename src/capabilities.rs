@@ -0,0 +1,62 @@
+use Capability::{Reasoning, Tools, Vision};
+
+/// Features a model may support beyond plain text chat
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Capability {
+    Vision,
+    Tools,
+    Reasoning,
+}
+
+impl Capability {
+    pub const ALL: &'static [Capability] = &[Capability::Vision, Capability::Tools, Capability::Reasoning];
+
+    pub fn name(self) -> &'static str {
+        match self {
+            Capability::Vision => "vision",
+            Capability::Tools => "tools",
+            Capability::Reasoning => "reasoning",
+        }
+    }
+}
+
+/// Model name prefixes and what those models are known to support. The first matching prefix wins,
+/// so more specific prefixes go before the general ones. Add new model families here.
+const REGISTRY: &[(&str, &[Capability])] = &[
+    ("gpt-4o", &[Vision, Tools]),
+    ("gpt-4.1", &[Vision, Tools]),
+    ("gpt-4-turbo", &[Vision, Tools]),
+    ("gpt-4", &[Tools]),
+    ("gpt-3.5", &[Tools]),
+    ("o1-mini", &[Reasoning]),
+    ("o1", &[Vision, Reasoning]),
+    ("o3-mini", &[Tools, Reasoning]),
+    ("o3", &[Vision, Tools, Reasoning]),
+    ("o4", &[Vision, Tools, Reasoning]),
+    ("claude-3-7", &[Vision, Tools, Reasoning]),
+    ("claude-3-5-haiku", &[Tools]),
+    ("claude-3", &[Vision, Tools]),
+    ("claude", &[Vision, Tools, Reasoning]),
+    ("gemini-2.5", &[Vision, Tools, Reasoning]),
+    ("gemini", &[Vision, Tools]),
+    ("deepseek-r1", &[Reasoning]),
+    ("deepseek-reasoner", &[Reasoning]),
+    ("qwq", &[Reasoning]),
+    ("qwen3", &[Tools, Reasoning]),
+    ("llama3.2-vision", &[Vision, Tools]),
+    ("llama3.1", &[Tools]),
+    ("llama3.2", &[Tools]),
+    ("llava", &[Vision]),
+    ("gemma3", &[Vision]),
+    ("mistral", &[Tools]),
+];
+
+/// Guesses the capabilities of a model from its name. Provider prefixes ("openai/gpt-4o")
+/// and Ollama tags ("llava:13b") are ignored. Returns None for unknown models.
+pub fn lookup(model: &str) -> Option<&'static [Capability]> {
+    let name = model.rsplit('/').next().unwrap_or(model);
+    let name = name.split(':').next().unwrap_or(name).to_lowercase();
+    REGISTRY.iter()
+        .find(|(prefix, _)| name.starts_with(prefix))
+        .map(|(_, capabilities)| *capabilities)
+}
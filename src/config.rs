@@ -1,6 +1,13 @@
-use serde::Deserialize;
-use std::{collections::HashMap, fs::File, io::Read, path::{Path, PathBuf}};
+use serde::{Deserialize, Serialize};
+use std::{collections::{HashMap, hash_map::RandomState}, fs::File, hash::{BuildHasher, Hasher}, io::Read, path::{Path, PathBuf}};
 use anyhow::{Context, Result, bail};
+use rust_i18n::t;
+
+/// One hour
+const DEFAULT_MODEL_CACHE_TTL: u64 = 3600;
+
+/// Common ways models phrase a refusal, in English
+const DEFAULT_REFUSAL_PATTERN: &str = r"(?i)\b(I can(no|'|’)?t (help|assist) with|I('m| am) (sorry|unable|not able)|I (won't|will not) be able to|I must decline)";
 
 #[derive(Debug, Deserialize, Clone)]
 pub struct Config {
@@ -8,24 +15,340 @@ pub struct Config {
     pub default_prompt: String,
     pub system_prompts: HashMap<String, String>,
     pub services: HashMap<String, Service>,
+    pub examples: HashMap<String, Vec<Message>>,
+    /// User prompt templates for --template, inline or "file:<path>"
+    pub templates: HashMap<String, String>,
+    /// Seconds a fetched model list stays cached (0 disables the cache)
+    pub model_cache_ttl: u64,
+    /// Regex telling --retry-on-refusal that a response is a refusal
+    pub refusal_pattern: String,
+    /// Prices per model, to estimate what a query cost
+    pub pricing: HashMap<String, ModelPrice>,
+    /// Reuse stored answers to repeated queries, as with --cache
+    pub cache: bool,
+    /// Keep the reasoning of the answers in stored conversations, as with --keep-thinking
+    pub keep_thinking: bool,
+    /// File every query and its answer are appended to, one JSON object per line
+    pub log_file: Option<String>,
+    /// Label from --tag, written with each logged query
+    #[serde(skip)]
+    pub tag: Option<String>,
+    /// Services asked in order when the query's service fails or its answer isn't accepted
+    pub fallback_services: Vec<String>,
+    /// Request parameters for every service that doesn't set its own
+    pub defaults: Defaults,
+    /// Overrides stored with --remember, applied between the config and the command line
+    #[serde(skip)]
+    pub remembered: Remembered,
+    /// Global config file that was merged in, if any
+    #[serde(skip)]
+    pub global_path: Option<PathBuf>,
+    /// Local (user or explicit) config file that was merged in, if any
+    #[serde(skip)]
+    pub local_path: Option<PathBuf>,
 }
 
-#[derive(Debug, Deserialize, Clone)]
+/// Sticky overrides kept in the state directory by --remember until --forget.
+/// The model and prompt only apply while the remembered service (if any) is the one in use.
+#[derive(Debug, Deserialize, Serialize, Clone, Default, PartialEq)]
+pub struct Remembered {
+    pub service: Option<String>,
+    pub model: Option<String>,
+    pub prompt: Option<String>,
+}
+
+impl Remembered {
+    pub fn is_empty(&self) -> bool {
+        *self == Self::default()
+    }
+
+    /// Whether the remembered model and prompt apply to the named service
+    pub fn applies_to(&self, service: &str) -> bool {
+        self.service.as_deref().is_none_or(|remembered| remembered == service)
+    }
+}
+
+#[derive(Debug, Deserialize, Serialize, Clone, Default)]
 pub struct Service {
+    /// Another service whose settings fill in the ones this service doesn't set
+    pub extends: Option<String>,
     pub url: Option<String>,
+    /// Required, unless inherited through `extends`
+    #[serde(default)]
     pub class: String, // "openai" or "ollama"
     pub model: Option<String>,
+    /// Relative weights for picking a model at random on each run, taking precedence over `model`
+    pub model_weights: Option<HashMap<String, f64>>,
     pub api_key: Option<String>,
+    /// Models this service may be used with; any other is refused
+    pub allowed_models: Option<Vec<String>>,
     pub system_prompt: Option<String>,
     pub description: Option<String>,
+    pub query_params: Option<HashMap<String, String>>,
+    /// Path of the chat endpoint, replacing the class default (e.g. "/v1/chat/completions")
+    #[serde(alias = "chat_path")]
+    pub completions_path: Option<String>,
+    /// Path of the model listing endpoint, replacing the class default (e.g. "/v1/models")
+    pub models_path: Option<String>,
+    /// Where the model's reasoning comes from: "tags", "field", "none" or "auto" (the class default)
+    pub reasoning_mode: Option<ReasoningMode>,
+    /// JSON request body with {{model}}, {{system}}, {{prompt}} and {{messages}} placeholders,
+    /// sent instead of the one the driver builds
+    pub body_template: Option<String>,
+    /// Seconds to wait for a connection and for each read of the answer (default 120)
+    pub timeout: Option<u64>,
+    /// Times to retry a request rejected with 429 or a 5xx status (default 0)
+    pub max_retries: Option<u32>,
+    /// Sampling temperature sent with every request
+    pub temperature: Option<f64>,
+    /// Cap on the length of the answer, in tokens
+    pub max_tokens: Option<u32>,
+    /// Sequences that end the answer when the model produces them
+    pub stop: Option<Vec<String>>,
+    /// API version sent with every request to an `azure` service (e.g. "2024-10-21")
+    pub api_version: Option<String>,
+    /// App URL sent as `HTTP-Referer` by an `openrouter` service
+    pub referer: Option<String>,
+    /// App name sent as `X-Title` by an `openrouter` service
+    pub title: Option<String>,
+    /// How long an `ollama` service keeps the model loaded after a request ("10m", or seconds)
+    pub keep_alive: Option<serde_json::Value>,
+    /// Model options sent as is by an `ollama` service (e.g. `num_ctx`, `seed`)
+    pub options: Option<HashMap<String, serde_json::Value>>,
+    /// Value sent as the Host header instead of the one taken from `url`
+    pub host_header: Option<String>,
+    /// Extra headers sent with every request (e.g. for an API gateway). They can't replace the authentication headers
+    pub headers: Option<HashMap<String, String>>,
+    /// PEM bundle with extra CA certificates to trust
+    pub ca_cert: Option<String>,
+    /// Skip TLS certificate verification. Dangerous: only for internal self-signed endpoints
+    pub danger_accept_invalid_certs: Option<bool>,
+}
+
+/// How a driver separates the model's reasoning from its answer
+#[derive(Debug, Deserialize, Serialize, Clone, Copy, PartialEq, Eq, Default)]
+#[serde(rename_all = "lowercase")]
+pub enum ReasoningMode {
+    /// Whatever the service class does natively
+    #[default]
+    Auto,
+    /// A <think>...</think> block at the start of the content
+    Tags,
+    /// A separate field of the API response
+    Field,
+    /// No extraction: the content is returned as is
+    None,
+}
+
+impl Service {
+    /// Fills in every setting this service leaves unset with the one from `parent`
+    fn inherit(&mut self, parent: &Service) {
+        // Destructured so a new field can't be forgotten here
+        let Service {
+            extends: _, url, class, model, model_weights, api_key, allowed_models, system_prompt, description,
+            query_params, completions_path, models_path, reasoning_mode, body_template, timeout, max_retries,
+            temperature, max_tokens, stop, api_version, referer, title, keep_alive, options,
+            host_header, headers, ca_cert, danger_accept_invalid_certs,
+        } = parent;
+
+        if self.class.is_empty() {
+            self.class = class.clone();
+        }
+        self.url = self.url.take().or_else(|| url.clone());
+        self.model = self.model.take().or_else(|| model.clone());
+        self.model_weights = self.model_weights.take().or_else(|| model_weights.clone());
+        self.api_key = self.api_key.take().or_else(|| api_key.clone());
+        self.allowed_models = self.allowed_models.take().or_else(|| allowed_models.clone());
+        self.system_prompt = self.system_prompt.take().or_else(|| system_prompt.clone());
+        self.description = self.description.take().or_else(|| description.clone());
+        self.query_params = self.query_params.take().or_else(|| query_params.clone());
+        self.completions_path = self.completions_path.take().or_else(|| completions_path.clone());
+        self.models_path = self.models_path.take().or_else(|| models_path.clone());
+        self.reasoning_mode = self.reasoning_mode.or(*reasoning_mode);
+        self.body_template = self.body_template.take().or_else(|| body_template.clone());
+        self.timeout = self.timeout.or(*timeout);
+        self.max_retries = self.max_retries.or(*max_retries);
+        self.temperature = self.temperature.or(*temperature);
+        self.max_tokens = self.max_tokens.or(*max_tokens);
+        self.stop = self.stop.take().or_else(|| stop.clone());
+        self.api_version = self.api_version.take().or_else(|| api_version.clone());
+        self.referer = self.referer.take().or_else(|| referer.clone());
+        self.title = self.title.take().or_else(|| title.clone());
+        self.keep_alive = self.keep_alive.take().or_else(|| keep_alive.clone());
+        self.options = self.options.take().or_else(|| options.clone());
+        self.host_header = self.host_header.take().or_else(|| host_header.clone());
+        self.headers = self.headers.take().or_else(|| headers.clone());
+        self.ca_cert = self.ca_cert.take().or_else(|| ca_cert.clone());
+        self.danger_accept_invalid_certs = self.danger_accept_invalid_certs.or(*danger_accept_invalid_certs);
+    }
+
+    /// This service with each `${NAME}` in its url, api_key and headers replaced by the environment
+    /// variable NAME. Done only for the service a command uses, so an unset variable doesn't break the others.
+    pub fn with_env_vars(&self, name: &str) -> Result<Service> {
+        let mut service = self.clone();
+        if let Some(api_key) = &self.api_key {
+            service.api_key = Some(expand_env_vars(api_key, name, "api_key")?);
+        }
+        if let Some(url) = &self.url {
+            service.url = Some(expand_env_vars(url, name, "url")?);
+        }
+        if let Some(headers) = service.headers.as_mut() {
+            for (header, value) in headers.iter_mut() {
+                *value = expand_env_vars(value, name, &format!("headers.{}", header))?;
+            }
+        }
+        Ok(service)
+    }
+
+    /// This service with the config's `defaults` filling in the request parameters it doesn't set
+    pub fn with_defaults(&self, defaults: &Defaults) -> Service {
+        Service {
+            temperature: self.temperature.or(defaults.temperature),
+            max_tokens: self.max_tokens.or(defaults.max_tokens),
+            stop: self.stop.clone().or_else(|| defaults.stop.clone()),
+            ..self.clone()
+        }
+    }
+
+    /// Picks one of `model_weights` at random, in proportion to its weight.
+    /// Returns None when there are no weights or none of them is positive.
+    pub fn pick_weighted_model(&self) -> Option<&str> {
+        let weights: Vec<(&String, f64)> = self.model_weights.as_ref()?
+            .iter()
+            .filter(|(_, weight)| weight.is_finite() && **weight > 0.0)
+            .map(|(model, weight)| (model, *weight))
+            .collect();
+        let total: f64 = weights.iter().map(|(_, weight)| weight).sum();
+        if total <= 0.0 {
+            return None;
+        }
+
+        // RandomState is randomly seeded per process, which is all the randomness needed here
+        let random = RandomState::new().build_hasher().finish();
+        let mut point = (random as f64 / u64::MAX as f64) * total;
+        for (model, weight) in &weights {
+            if point < *weight {
+                return Some(model);
+            }
+            point -= weight;
+        }
+        weights.last().map(|(model, _)| model.as_str())
+    }
+}
+
+/// Settings given at the top level of the config, used by every service that doesn't set them.
+/// The command line still wins over both.
+#[derive(Debug, Deserialize, Serialize, Clone, Default, PartialEq)]
+pub struct Defaults {
+    pub url: Option<String>,
+    pub api_key: Option<String>,
+    pub temperature: Option<f64>,
+    pub max_tokens: Option<u32>,
+    pub stop: Option<Vec<String>>,
+}
+
+impl Defaults {
+    /// Settings in `other` replace these one by one
+    fn merge(self, other: Defaults) -> Self {
+        Self {
+            url: other.url.or(self.url),
+            api_key: other.api_key.or(self.api_key),
+            temperature: other.temperature.or(self.temperature),
+            max_tokens: other.max_tokens.or(self.max_tokens),
+            stop: other.stop.or(self.stop),
+        }
+    }
+}
+
+/// Price of a model in USD per million tokens
+#[derive(Debug, Deserialize, Serialize, Clone, Copy, Default, PartialEq)]
+pub struct ModelPrice {
+    pub input: f64,
+    pub output: f64,
+}
+
+/// A single chat turn sent to the LLM (the system prompt is kept apart by the drivers)
+#[derive(Debug, Deserialize, Serialize, Clone)]
+pub struct Message {
+    pub role: String, // "user" or "assistant"
+    pub content: String,
+    /// Images sent along with the text
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub images: Vec<ImageInput>,
+}
+
+impl Message {
+    pub const ROLES: &'static [&'static str] = &["user", "assistant"];
+
+    pub fn user(content: &str) -> Self {
+        Self { role: "user".to_string(), content: content.to_string(), images: Vec::new() }
+    }
+
+    pub fn assistant(content: &str) -> Self {
+        Self { role: "assistant".to_string(), content: content.to_string(), images: Vec::new() }
+    }
+
+    pub fn has_valid_role(&self) -> bool {
+        Self::ROLES.contains(&self.role.as_str())
+    }
+}
+
+/// An image attached to a prompt, base64-encoded
+#[derive(Debug, Deserialize, Serialize, Clone)]
+pub struct ImageInput {
+    pub mime_type: String,
+    pub data: String,
+}
+
+impl ImageInput {
+    /// File extensions accepted as images and their MIME types
+    const FORMATS: &'static [(&'static str, &'static str)] = &[
+        ("png", "image/png"),
+        ("jpg", "image/jpeg"),
+        ("jpeg", "image/jpeg"),
+        ("gif", "image/gif"),
+        ("webp", "image/webp"),
+    ];
+
+    /// Reads an image file. The format is taken from the file extension.
+    pub fn load(path: &Path) -> Result<Self> {
+        let extension = path.extension().and_then(|ext| ext.to_str()).unwrap_or_default().to_lowercase();
+        let Some((_, mime_type)) = Self::FORMATS.iter().find(|(ext, _)| *ext == extension) else {
+            let supported: Vec<&str> = Self::FORMATS.iter().map(|(ext, _)| *ext).collect();
+            bail!("{}", t!("image_unsupported_format", path = path.display(), supported = supported.join(", ")));
+        };
+        let bytes = std::fs::read(path).with_context(|| t!("image_unreadable", path = path.display()))?;
+
+        use base64::Engine;
+        Ok(Self { mime_type: mime_type.to_string(), data: base64::engine::general_purpose::STANDARD.encode(bytes) })
+    }
+
+    /// The image as a `data:` URL
+    pub fn data_url(&self) -> String {
+        format!("data:{};base64,{}", self.mime_type, self.data)
+    }
 }
 
-#[derive(Debug, Deserialize, Clone, Default)]
+#[derive(Debug, Deserialize, Serialize, Clone, Default)]
 struct PartialConfig {
+    /// Files merged in before this one, relative to it
+    pub include: Option<Vec<String>>,
     pub default_service: Option<String>,
     pub default_prompt: Option<String>,
     pub system_prompts: Option<HashMap<String, String>>,
     pub services: Option<HashMap<String, Service>>,
+    pub examples: Option<HashMap<String, Vec<Message>>>,
+    pub templates: Option<HashMap<String, String>>,
+    pub model_cache_ttl: Option<u64>,
+    pub refusal_pattern: Option<String>,
+    pub pricing: Option<HashMap<String, ModelPrice>>,
+    pub cache: Option<bool>,
+    pub keep_thinking: Option<bool>,
+    pub log_file: Option<String>,
+    pub fallback_services: Option<Vec<String>>,
+    pub defaults: Option<Defaults>,
+    /// Named sets of settings applied over the rest of the file with --profile
+    pub profiles: Option<HashMap<String, PartialConfig>>,
 }
 
 impl PartialConfig {
@@ -36,6 +359,24 @@ impl PartialConfig {
         if let Some(dp) = other.default_prompt {
             self.default_prompt = Some(dp);
         }
+        if let Some(ttl) = other.model_cache_ttl {
+            self.model_cache_ttl = Some(ttl);
+        }
+        if let Some(pattern) = other.refusal_pattern {
+            self.refusal_pattern = Some(pattern);
+        }
+        if let Some(cache) = other.cache {
+            self.cache = Some(cache);
+        }
+        if let Some(keep_thinking) = other.keep_thinking {
+            self.keep_thinking = Some(keep_thinking);
+        }
+        if let Some(log_file) = other.log_file {
+            self.log_file = Some(log_file);
+        }
+        if let Some(fallback_services) = other.fallback_services {
+            self.fallback_services = Some(fallback_services);
+        }
         
         if let Some(other_prompts) = other.system_prompts {
              let mut current = self.system_prompts.unwrap_or_default();
@@ -48,73 +389,256 @@ impl PartialConfig {
              current.extend(other_services);
              self.services = Some(current);
         }
+
+        if let Some(other_examples) = other.examples {
+             let mut current = self.examples.unwrap_or_default();
+             current.extend(other_examples);
+             self.examples = Some(current);
+        }
+
+        if let Some(other_templates) = other.templates {
+             let mut current = self.templates.unwrap_or_default();
+             current.extend(other_templates);
+             self.templates = Some(current);
+        }
+
+        if let Some(other_pricing) = other.pricing {
+             let mut current = self.pricing.unwrap_or_default();
+             current.extend(other_pricing);
+             self.pricing = Some(current);
+        }
+
+        if let Some(other_defaults) = other.defaults {
+             self.defaults = Some(self.defaults.unwrap_or_default().merge(other_defaults));
+        }
+
+        // A profile defined in several files is merged like the files themselves
+        if let Some(other_profiles) = other.profiles {
+             let mut current = self.profiles.unwrap_or_default();
+             for (name, profile) in other_profiles {
+                 let merged = match current.remove(&name) {
+                     Some(existing) => existing.merge(profile),
+                     None => profile,
+                 };
+                 current.insert(name, merged);
+             }
+             self.profiles = Some(current);
+        }
         
         self
     }
 
+    /// Applies the named profile over the rest of the configuration
+    fn with_profile(mut self, name: &str) -> Result<Self> {
+        let mut profiles = self.profiles.take().unwrap_or_default();
+        let Some(profile) = profiles.remove(name) else {
+            let mut available: Vec<_> = profiles.into_keys().collect();
+            available.sort();
+            bail!("{}", t!("profile_not_found", name = name, available = available.join(", ")));
+        };
+        Ok(self.merge(profile))
+    }
+
     fn try_into_config(self) -> Result<Config> {
         let default_service = self.default_service.context("Missing 'default_service' in configuration")?;
         let default_prompt = self.default_prompt.context("Missing 'default_prompt' in configuration")?;
         let system_prompts = self.system_prompts.unwrap_or_default();
-        let services = self.services.unwrap_or_default();
+        let defaults = self.defaults.unwrap_or_default();
+        let mut services = resolve_extends(self.services.unwrap_or_default())?;
+        for service in services.values_mut() {
+            // Unlike the request parameters, these are needed before any request (to list models, to lint...)
+            service.url = service.url.take().or_else(|| defaults.url.clone());
+            service.api_key = service.api_key.take().or_else(|| defaults.api_key.clone());
+        }
+        let examples = self.examples.unwrap_or_default();
+        let templates = self.templates.unwrap_or_default();
+        let model_cache_ttl = self.model_cache_ttl.unwrap_or(DEFAULT_MODEL_CACHE_TTL);
+        let refusal_pattern = self.refusal_pattern.unwrap_or_else(|| DEFAULT_REFUSAL_PATTERN.to_string());
+        let pricing = self.pricing.unwrap_or_default();
+        let cache = self.cache.unwrap_or(false);
+        let keep_thinking = self.keep_thinking.unwrap_or(false);
 
         Ok(Config {
             default_service,
             default_prompt,
             system_prompts,
             services,
+            examples,
+            templates,
+            model_cache_ttl,
+            refusal_pattern,
+            pricing,
+            cache,
+            keep_thinking,
+            log_file: self.log_file,
+            tag: None,
+            fallback_services: self.fallback_services.unwrap_or_default(),
+            defaults,
+            remembered: Remembered::default(),
+            global_path: None,
+            local_path: None,
         })
     }
 }
 
+/// Applies `extends`: each service inherits the settings it doesn't set from its parent, the
+/// parent's parent and so on. A missing parent or a circular chain is an error.
+fn resolve_extends(services: HashMap<String, Service>) -> Result<HashMap<String, Service>> {
+    let mut resolved = HashMap::with_capacity(services.len());
+    // Sorted so errors name the same services on every run
+    let mut names: Vec<&String> = services.keys().collect();
+    names.sort();
+    for name in names {
+        let own = &services[name];
+        let mut service = own.clone();
+        let mut chain = vec![name.as_str()];
+        let mut parent_name = own.extends.as_deref();
+        while let Some(parent) = parent_name {
+            if chain.contains(&parent) {
+                chain.push(parent);
+                bail!("{}", t!("service_extends_cycle", chain = chain.join(" -> ")));
+            }
+            let parent_service = services.get(parent)
+                .with_context(|| t!("service_extends_unknown", service = chain[chain.len() - 1], parent = parent))?;
+            service.inherit(parent_service);
+            chain.push(parent);
+            parent_name = parent_service.extends.as_deref();
+        }
+        if service.class.is_empty() {
+            bail!("{}", t!("service_class_missing", service = name));
+        }
+        resolved.insert(name.clone(), service);
+    }
+    Ok(resolved)
+}
+
+/// Replaces each `${NAME}` in a service field with the value of the environment variable NAME.
+/// Text without `${` is returned untouched.
+fn expand_env_vars(value: &str, service: &str, field: &str) -> Result<String> {
+    let mut expanded = String::with_capacity(value.len());
+    let mut rest = value;
+    while let Some(start) = rest.find("${") {
+        let Some(len) = rest[start + 2..].find('}') else {
+            break;
+        };
+        let name = &rest[start + 2..start + 2 + len];
+        let var = std::env::var(name).map_err(|_| anyhow::anyhow!("{}", t!("env_var_missing", var = name, service = service, field = field)))?;
+        expanded.push_str(&rest[..start]);
+        expanded.push_str(&var);
+        rest = &rest[start + 3 + len..];
+    }
+    expanded.push_str(rest);
+    Ok(expanded)
+}
+
 impl Config {
-    pub fn load(explicit_path: Option<String>) -> Result<Self> {
+    /// Maps each system prompt key to the services whose prompt (or the default) resolves to it.
+    /// Prompts used by no service are absent from the map.
+    pub fn prompt_users(&self) -> HashMap<String, Vec<String>> {
+        let mut users: HashMap<String, Vec<String>> = HashMap::new();
+        for (name, service) in &self.services {
+            let sys_ref = service.system_prompt.as_ref().unwrap_or(&self.default_prompt);
+            if self.system_prompts.contains_key(sys_ref) {
+                users.entry(sys_ref.clone()).or_default().push(name.clone());
+            }
+        }
+        for services in users.values_mut() {
+            services.sort();
+        }
+        users
+    }
+
+    /// Returns the named few-shot example set, checking every message has a valid role.
+    pub fn example_set(&self, name: &str) -> Result<&[Message]> {
+        let examples = self.examples.get(name).context(t!("examples_not_found", name = name))?;
+        if let Some(bad) = examples.iter().find(|m| !m.has_valid_role()) {
+            bail!("{}", t!("invalid_example_role", name = name, role = bad.role, valid = Message::ROLES.join(", ")));
+        }
+        Ok(examples)
+    }
+
+    /// Estimated cost in USD of a request to `model`, if the model has a price
+    pub fn estimate_cost(&self, model: &str, input_tokens: u64, output_tokens: u64) -> Option<f64> {
+        let price = self.pricing.get(model)?;
+        Some((input_tokens as f64 * price.input + output_tokens as f64 * price.output) / 1_000_000.0)
+    }
+
+    /// Returns the text of the named user prompt template, reading it from disk for "file:<path>".
+    pub fn prompt_template(&self, name: &str) -> Result<String> {
+        let template = self.templates.get(name).context(t!("template_not_found", name = name))?;
+        match template.strip_prefix("file:") {
+            Some(path) => std::fs::read_to_string(path.trim()).with_context(|| t!("template_file_unreadable", name = name, path = path.trim())),
+            None => Ok(template.clone()),
+        }
+    }
+
+    /// Merges the given files in order (over the global config unless `no_global`) and
+    /// returns the result as YAML. Unlike `load`, the merged result doesn't need to be complete.
+    pub fn merged_yaml(paths: &[String], no_global: bool) -> Result<String> {
+        let mut merged = PartialConfig::default();
+
+        if !no_global
+            && let Some(global_path) = Self::get_global_config_path()
+            && global_path.exists() {
+                 let partial = Self::load_partial(&global_path).context(format!("Failed to load config at {:?}", global_path))?;
+                 merged = merged.merge(partial);
+        }
+
+        for path in paths {
+             let partial = if path == "-" {
+                 Self::load_partial_stdin().context(t!("failed_read_config_stdin"))?
+             } else {
+                 Self::load_partial(Path::new(path)).context(format!("Failed to load config at {:?}", path))?
+             };
+             merged = merged.merge(partial);
+        }
+
+        let value = tidy_yaml(serde_yaml::to_value(&merged)?);
+        Ok(serde_yaml::to_string(&value)?)
+    }
+
+    /// Loads and merges the global and local configuration files, then applies `profile` if given.
+    /// An explicit path of "-" reads the local configuration from stdin.
+    /// A global config that fails to parse is skipped with a warning, or is an error if `strict`.
+    pub fn load(explicit_path: Option<String>, no_global: bool, strict: bool, profile: Option<&str>) -> Result<Self> {
         let mut final_partial = PartialConfig::default();
         let mut loaded_any = false;
+        let mut global_loaded = None;
 
         // 1. Load Global Config
-        if let Some(global_path) = Self::get_global_config_path() {
-            if global_path.exists() {
-                 if let Ok(partial) = Self::load_partial(&global_path) {
-                     final_partial = final_partial.merge(partial);
-                     loaded_any = true;
-                     #[cfg(debug_assertions)]
-                     eprintln!("Loaded global config: {:?}", global_path);
+        if !no_global
+            && let Some(global_path) = Self::get_global_config_path()
+            && global_path.exists() {
+                 match Self::load_partial(&global_path) {
+                     Ok(partial) => {
+                         final_partial = final_partial.merge(partial);
+                         loaded_any = true;
+                         #[cfg(debug_assertions)]
+                         eprintln!("Loaded global config: {:?}", global_path);
+                         global_loaded = Some(global_path);
+                     },
+                     Err(err) if strict => {
+                         bail!("Failed to load config at {:?}: {}", global_path, err);
+                     },
+                     Err(err) => {
+                         eprintln!("{}", t!("global_config_skipped", path = global_path.display(), error = err));
+                     },
                  }
-            }
         }
 
         // 2. Determine Local Config Path
-        let local_path_buf;
-        let local_path = if let Some(path) = explicit_path {
-            local_path_buf = PathBuf::from(path);
-            Some(local_path_buf.as_path())
-        } else {
-            // Try current directory
-            let cwd_config = Path::new("askme.yml");
-            if cwd_config.exists() {
-                 local_path_buf = cwd_config.to_path_buf();
-                 Some(local_path_buf.as_path())
-            } else {
-                 // Try ~/.config/askme.yml
-                 if let Some(config_dir) = dirs::config_dir() {
-                     local_path_buf = config_dir.join("askme.yml");
-                     if local_path_buf.exists() {
-                         Some(local_path_buf.as_path())
-                     } else {
-                         None
-                     }
-                 } else {
-                     None
-                 }
-            }
-        };
+        let local_path_buf = Self::local_config_path(explicit_path);
+        let local_path = local_path_buf.as_deref();
 
         #[cfg(debug_assertions)]
         eprintln!("Loaded local config: {:?}", local_path);
 
         if let Some(path) = local_path {
-             let partial = Self::load_partial(path).context(format!("Failed to load config at {:?}", path))?;
+             let partial = if path == Path::new("-") {
+                 Self::load_partial_stdin().context(t!("failed_read_config_stdin"))?
+             } else {
+                 Self::load_partial(path).context(format!("Failed to load config at {:?}", path))?
+             };
              final_partial = final_partial.merge(partial);
         } else if !loaded_any {
              // If no explicit path gave and we didn't find any default config files
@@ -123,7 +647,43 @@ impl Config {
              bail!("No configuration file found. Checked ./askme.yml, ~/.config/askme.yml, and global locations");
         }
 
-        final_partial.try_into_config()
+        if let Some(profile) = profile {
+            final_partial = final_partial.with_profile(profile)?;
+        }
+
+        let mut config = final_partial.try_into_config()?;
+        config.global_path = global_loaded;
+        config.local_path = local_path.map(Path::to_path_buf);
+        Ok(config)
+    }
+
+    /// The local config file: the explicit path if given, else ./askme.yml or ~/.config/askme.yml when they exist.
+    pub fn local_config_path(explicit_path: Option<String>) -> Option<PathBuf> {
+        if let Some(path) = explicit_path {
+            return Some(PathBuf::from(path));
+        }
+
+        // Try current directory
+        let cwd_config = Path::new("askme.yml");
+        if cwd_config.exists() {
+            return Some(cwd_config.to_path_buf());
+        }
+
+        // Try ~/.config/askme.yml
+        dirs::config_dir()
+            .map(|config_dir| config_dir.join("askme.yml"))
+            .filter(|path| path.exists())
+    }
+
+    /// Names of the fields recognized at the top level of a config file and inside a service
+    pub fn known_fields() -> (Vec<String>, Vec<String>) {
+        fn keys<T: Serialize>(value: &T) -> Vec<String> {
+            match serde_yaml::to_value(value) {
+                Ok(serde_yaml::Value::Mapping(map)) => map.keys().filter_map(|k| k.as_str().map(str::to_string)).collect(),
+                _ => Vec::new(),
+            }
+        }
+        (keys(&PartialConfig::default()), keys(&Service::default()))
     }
 
     #[inline]
@@ -148,10 +708,67 @@ impl Config {
     }
 
     fn load_partial(path: &Path) -> Result<PartialConfig> {
+        Self::load_partial_included(path, &mut Vec::new())
+    }
+
+    /// Loads a config file with the files it includes. `chain` holds the files including this one,
+    /// so a file that ends up including itself is caught.
+    fn load_partial_included(path: &Path, chain: &mut Vec<PathBuf>) -> Result<PartialConfig> {
         let mut file = File::open(path)?;
         let mut contents = String::new();
         file.read_to_string(&mut contents)?;
-        let partial: PartialConfig = serde_yaml::from_str(&contents)?;
+
+        let canonical = path.canonicalize()?;
+        if chain.contains(&canonical) {
+            bail!("{}", t!("config_include_cycle", path = path.display()));
+        }
+        chain.push(canonical);
+        let partial = Self::parse_partial(&contents)
+            .and_then(|partial| Self::with_includes(partial, path.parent().unwrap_or(Path::new("")), chain));
+        chain.pop();
+        partial
+    }
+
+    /// Merges the files `partial` includes, in order, under `partial` itself
+    fn with_includes(mut partial: PartialConfig, base_dir: &Path, chain: &mut Vec<PathBuf>) -> Result<PartialConfig> {
+        let mut merged = PartialConfig::default();
+        for include in partial.include.take().unwrap_or_default() {
+            let path = base_dir.join(&include);
+            let included = Self::load_partial_included(&path, chain)
+                .with_context(|| t!("config_include_failed", path = path.display()))?;
+            merged = merged.merge(included);
+        }
+        Ok(merged.merge(partial))
+    }
+
+    /// Reads a config from stdin. Its includes are relative to the current directory.
+    fn load_partial_stdin() -> Result<PartialConfig> {
+        let mut contents = String::new();
+        std::io::stdin().read_to_string(&mut contents)?;
+        Self::with_includes(Self::parse_partial(&contents)?, Path::new(""), &mut Vec::new())
+    }
+
+    /// Parses a config file, expanding YAML merge keys (`<<: *anchor`) first
+    fn parse_partial(contents: &str) -> Result<PartialConfig> {
+        let mut value: serde_yaml::Value = serde_yaml::from_str(contents)?;
+        value.apply_merge()?;
+        let partial: PartialConfig = serde_yaml::from_value(value)?;
         Ok(partial)
     }
 }
+
+/// Drops null entries and sorts mapping keys so merged output is stable and readable
+fn tidy_yaml(value: serde_yaml::Value) -> serde_yaml::Value {
+    match value {
+        serde_yaml::Value::Mapping(map) => {
+            let mut entries: Vec<(serde_yaml::Value, serde_yaml::Value)> = map.into_iter()
+                .filter(|(_, v)| !v.is_null())
+                .map(|(k, v)| (k, tidy_yaml(v)))
+                .collect();
+            entries.sort_by(|(a, _), (b, _)| a.as_str().cmp(&b.as_str()));
+            serde_yaml::Value::Mapping(entries.into_iter().collect())
+        },
+        serde_yaml::Value::Sequence(items) => serde_yaml::Value::Sequence(items.into_iter().map(tidy_yaml).collect()),
+        other => other,
+    }
+}
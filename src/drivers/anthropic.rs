@@ -1,73 +1,147 @@
 use anyhow::{Result, bail, Context};
 use serde_json::json;
 use rust_i18n::t;
-use crate::config::Service;
-use super::LLMService;
+use crate::config::{Message, ReasoningMode, Service};
+use std::collections::HashMap;
+use super::{Completion, LLMService, Usage, ANTHROPIC_URL, build_agent, extract_reasoning, render_body_template, request_failed, resolve_api_key, send_with_retries, url_with_query, with_headers, with_host_header, with_query_params};
 
 pub struct AnthropicDriver {
     // URL is hardcoded
     api_key: String,
     model: String,
     system_prompt: String,
+    query_params: HashMap<String, String>,
+    host_header: Option<String>,
+    headers: HashMap<String, String>,
+    max_tokens: Option<u32>,
+    temperature: Option<f64>,
+    stop: Vec<String>,
+    max_retries: u32,
+    body_template: Option<String>,
+    reasoning_mode: ReasoningMode,
+    agent: ureq::Agent,
+    cache_prompt: bool,
+}
+
+impl AnthropicDriver {
+    /// Builds the request body: the configured `body_template`, or the standard Messages API payload
+    fn request_body(&self, messages: &[Message]) -> Result<serde_json::Value> {
+        // A cached system prompt has to be sent as a content block carrying the cache marker
+        let system = if self.cache_prompt {
+            json!([{"type": "text", "text": self.system_prompt, "cache_control": {"type": "ephemeral"}}])
+        } else {
+            json!(self.system_prompt)
+        };
+
+        // Images go in content blocks ahead of the text
+        let messages_json: Vec<serde_json::Value> = messages.iter().map(|m| {
+            if m.images.is_empty() {
+                return json!({ "role": m.role, "content": m.content });
+            }
+            let mut content: Vec<serde_json::Value> = m.images.iter()
+                .map(|image| json!({ "type": "image", "source": { "type": "base64", "media_type": image.mime_type, "data": image.data } }))
+                .collect();
+            content.push(json!({ "type": "text", "text": m.content }));
+            json!({ "role": m.role, "content": content })
+        }).collect();
+
+        let body = match &self.body_template {
+            Some(template) => render_body_template(template, &self.model, &self.system_prompt, messages)?,
+            None => {
+                let mut body = json!({
+                    "model": self.model,
+                    "system": system,
+                    "messages": messages_json,
+                    "max_tokens": self.max_tokens.unwrap_or(1024)
+                });
+                if let Some(temperature) = self.temperature {
+                    body["temperature"] = json!(temperature);
+                }
+                if !self.stop.is_empty() {
+                    body["stop_sequences"] = json!(self.stop);
+                }
+                body
+            },
+        };
+        Ok(body)
+    }
 }
 
 impl LLMService for AnthropicDriver {
     fn new(service: &Service, model: &str, system_prompt: &str) -> Result<Self> {
          let api_key = service.api_key.as_deref().context(t!("api_key_required", service = "Anthropic"))?;
+         let api_key = resolve_api_key(api_key, "Anthropic")?;
          
          Ok(Self {
-             api_key: api_key.to_string(),
+             api_key,
              model: model.to_string(),
              system_prompt: system_prompt.to_string(),
+             query_params: service.query_params.clone().unwrap_or_default(),
+             host_header: service.host_header.clone(),
+             headers: service.headers.clone().unwrap_or_default(),
+             max_tokens: service.max_tokens,
+             temperature: service.temperature,
+             stop: service.stop.clone().unwrap_or_default(),
+             max_retries: service.max_retries.unwrap_or(0),
+             body_template: service.body_template.clone(),
+             reasoning_mode: service.reasoning_mode.unwrap_or_default(),
+             agent: build_agent(service)?,
+             cache_prompt: false,
          })
     }
 
-    fn complete(&self, prompt: &str) -> Result<(String, Option<String>)> {
-        let base_url = "https://api.anthropic.com";
-        let endpoint = format!("{}/v1/messages", base_url);
-
-        let body = json!({
-            "model": self.model,
-            "system": self.system_prompt,
-            "messages": [
-                { "role": "user", "content": prompt }
-            ],
-            "max_tokens": 1024 
-        });
+    fn complete(&self, messages: &[Message]) -> Result<Completion> {
+        let endpoint = format!("{}/v1/messages", ANTHROPIC_URL);
+        let body = self.request_body(messages)?;
 
-        let res = ureq::post(&endpoint)
+        let mut req = with_host_header(with_query_params(self.agent.post(&endpoint), &self.query_params), &self.host_header)
             .set("x-api-key", &self.api_key)
             .set("anthropic-version", "2023-06-01")
-            .set("Content-Type", "application/json")
-            .send_json(body);
+            .set("Content-Type", "application/json");
+        if self.cache_prompt {
+            req = req.set("anthropic-beta", "prompt-caching-2024-07-31");
+        }
+
+        let req = with_headers(req, &self.headers);
+        let res = send_with_retries(&req, Some(&body), self.max_retries);
 
         match res {
             Ok(response) => {
                 let json: serde_json::Value = response.into_json().context("Failed to parse Anthropic response")?;
                 
-                let content = json["content"][0]["text"]
-                    .as_str()
-                    .map(|s| s.to_string())
-                    .context("Invalid response format from Anthropic")?;
-                
-                 if let Some(start) = content.find("<think>") {
-                     if let Some(end) = content.find("</think>") {
-                          let thinking = content[start + 7..end].trim().to_string();
-                          let response_part = content[end + 8..].trim().to_string();
-                          return Ok((response_part, Some(thinking)));
-                     }
+                let blocks = json["content"].as_array().context("Invalid response format from Anthropic")?;
+
+                // With extended thinking the reasoning comes in "thinking" blocks before the text ones
+                let mut content = String::new();
+                let mut thinking = String::new();
+                for block in blocks {
+                    match block["type"].as_str() {
+                        Some("text") => content.push_str(block["text"].as_str().unwrap_or_default()),
+                        Some("thinking") => thinking.push_str(block["thinking"].as_str().unwrap_or_default()),
+                        _ => {},
+                    }
                 }
 
-                Ok((content, None))
+                // Native thinking wins; the <think> scan is left for models that write tags in the text
+                let native = if thinking.is_empty() { ReasoningMode::Tags } else { ReasoningMode::Field };
+                let usage = Usage::parse(&json["usage"], "input_tokens", "output_tokens")
+                    .map(|usage| usage.with_cache(&json["usage"], "/cache_read_input_tokens", Some("/cache_creation_input_tokens")));
+                let truncated = json["stop_reason"] == "max_tokens";
+                Ok(Completion::from(extract_reasoning(self.reasoning_mode, native, content, Some(thinking))).with_usage(usage).with_truncated(truncated))
             },
             Err(ureq::Error::Status(code, response)) => {
                  let text = response.into_string().unwrap_or_default();
                  bail!("Anthropic API error: Status: {}, Body: {}", code, text);
             },
-            Err(e) => bail!("Request failed: {}", e),
+            Err(e) => Err(request_failed(e)),
         }
     }
 
+    fn build_request(&self, messages: &[Message]) -> Result<(String, serde_json::Value)> {
+        let endpoint = format!("{}/v1/messages", ANTHROPIC_URL);
+        Ok((url_with_query(&self.agent, &endpoint, &self.query_params), self.request_body(messages)?))
+    }
+
     fn model(&self) -> &str {
         &self.model
     }
@@ -76,14 +150,66 @@ impl LLMService for AnthropicDriver {
         &self.system_prompt
     }
 
+    fn default_model() -> Option<&'static str> {
+        Some("claude-3-5-sonnet-latest")
+    }
+
+    fn supports_prefill(&self) -> bool {
+        true
+    }
+
+    fn enable_prompt_caching(&mut self) -> bool {
+        self.cache_prompt = true;
+        true
+    }
+
+    fn set_max_tokens(&mut self, max_tokens: u32) {
+        self.max_tokens = Some(max_tokens);
+    }
+
+    fn set_temperature(&mut self, temperature: f64) {
+        self.temperature = Some(temperature);
+    }
+
+    fn set_stop(&mut self, stop: &[String]) {
+        self.stop = stop.to_vec();
+    }
+
+    fn append_system_prompt(&mut self, text: &str) {
+        self.system_prompt.push_str(text);
+    }
+
+    fn set_system_prompt(&mut self, text: &str) {
+        self.system_prompt = text.to_string();
+    }
+
+    fn model_info(&self, model: &str) -> Result<serde_json::Value> {
+        let endpoint = format!("{}/v1/models/{}", ANTHROPIC_URL, model);
+
+        let req = with_host_header(with_query_params(self.agent.get(&endpoint), &self.query_params), &self.host_header)
+             .set("x-api-key", &self.api_key)
+             .set("anthropic-version", "2023-06-01");
+        let req = with_headers(req, &self.headers);
+        match send_with_retries(&req, None, self.max_retries) {
+            Ok(response) => response.into_json().context("Failed to parse Anthropic model response"),
+            Err(ureq::Error::Status(404, _)) => bail!("{}", t!("model_info_not_found", model = model, service = "Anthropic")),
+            Err(ureq::Error::Status(code, response)) => {
+                 let text = response.into_string().unwrap_or_default();
+                 bail!("Anthropic API error: Status: {}, Body: {}", code, text)
+            },
+            Err(e) => Err(request_failed(e)),
+        }
+    }
+
     fn list_models(&self) -> Result<Vec<String>> {
-        let base_url = "https://api.anthropic.com";
+        let base_url = ANTHROPIC_URL;
         let endpoint = format!("{}/v1/models", base_url);
 
-        let res = ureq::get(&endpoint)
+        let req = with_host_header(with_query_params(self.agent.get(&endpoint), &self.query_params), &self.host_header)
              .set("x-api-key", &self.api_key)
-             .set("anthropic-version", "2023-06-01")
-             .call();
+             .set("anthropic-version", "2023-06-01");
+        let req = with_headers(req, &self.headers);
+        let res = send_with_retries(&req, None, self.max_retries);
 
         match res {
             Ok(response) => {
@@ -100,9 +226,12 @@ impl LLMService for AnthropicDriver {
             },
             Err(ureq::Error::Status(code, response)) => {
                  let text = response.into_string().unwrap_or_default();
-                 bail!("Anthropic API error: Status: {}, Body: {}", code, text);
+                 match code {
+                     404 | 405 => bail!("{}", t!("list_models_not_supported", service = "Anthropic")),
+                     _ => bail!("Anthropic API error: Status: {}, Body: {}", code, text),
+                 }
             },
-            Err(e) => bail!("Request failed: {}", e),
+            Err(e) => Err(request_failed(e)),
         }
     }
 }
@@ -0,0 +1,80 @@
+use anyhow::{Result, Context};
+use rust_i18n::t;
+use crate::config::{Message, Service};
+use std::io::Write;
+use super::{Completion, LLMService, openai::OpenAIDriver};
+
+/// Azure OpenAI. The request and response bodies are OpenAI's, but the deployment (the `model`)
+/// is part of the URL, the API version goes in the query and the key in an `api-key` header.
+pub struct AzureDriver {
+    inner: OpenAIDriver,
+}
+
+impl LLMService for AzureDriver {
+    fn new(service: &Service, model: &str, system_prompt: &str) -> Result<Self> {
+        let url = service.url.as_deref().context(t!("azure_field_required", field = "url"))?;
+        let api_version = service.api_version.as_deref().context(t!("azure_field_required", field = "api_version"))?;
+
+        // Paths and query parameters set in the service still win
+        let mut service = service.clone();
+        service.completions_path.get_or_insert_with(|| format!("/openai/deployments/{}/chat/completions", model));
+        service.models_path.get_or_insert_with(|| "/openai/models".to_string());
+        service.query_params.get_or_insert_default().entry("api-version".to_string()).or_insert_with(|| api_version.to_string());
+
+        Ok(Self {
+            inner: OpenAIDriver::compatible(&service, model, system_prompt, "Azure OpenAI", url)?.with_api_key_header("api-key"),
+        })
+    }
+
+    fn complete(&self, messages: &[Message]) -> Result<Completion> {
+        self.inner.complete(messages)
+    }
+
+    fn complete_streaming(&self, messages: &[Message], sink: &mut dyn Write) -> Result<Completion> {
+        self.inner.complete_streaming(messages, sink)
+    }
+
+    fn build_request(&self, messages: &[Message]) -> Result<(String, serde_json::Value)> {
+        self.inner.build_request(messages)
+    }
+
+    fn model(&self) -> &str {
+        self.inner.model()
+    }
+
+    fn system_prompt(&self) -> &str {
+        self.inner.system_prompt()
+    }
+
+    fn set_max_tokens(&mut self, max_tokens: u32) {
+        self.inner.set_max_tokens(max_tokens);
+    }
+
+    fn set_temperature(&mut self, temperature: f64) {
+        self.inner.set_temperature(temperature);
+    }
+
+    fn set_stop(&mut self, stop: &[String]) {
+        self.inner.set_stop(stop);
+    }
+
+    fn append_system_prompt(&mut self, text: &str) {
+        self.inner.append_system_prompt(text);
+    }
+
+    fn set_system_prompt(&mut self, text: &str) {
+        self.inner.set_system_prompt(text);
+    }
+
+    fn set_response_schema(&mut self, schema: &serde_json::Value) -> bool {
+        self.inner.set_response_schema(schema)
+    }
+
+    fn model_info(&self, model: &str) -> Result<serde_json::Value> {
+        self.inner.model_info(model)
+    }
+
+    fn list_models(&self) -> Result<Vec<String>> {
+        self.inner.list_models()
+    }
+}
@@ -1,74 +1,139 @@
 use anyhow::{Result, bail, Context};
 use serde_json::json;
 use rust_i18n::t;
-use crate::config::Service;
-use super::LLMService;
+use crate::config::{Message, ReasoningMode, Service};
+use std::collections::HashMap;
+use super::{Completion, LLMService, Usage, GEMINI_URL, build_agent, extract_reasoning, render_body_template, request_failed, resolve_api_key, send_with_retries, url_with_query, with_headers, with_host_header, with_query_params};
 
 pub struct GeminiDriver {
     // URL is hardcoded
     api_key: String,
     model: String,
     system_prompt: String,
+    query_params: HashMap<String, String>,
+    host_header: Option<String>,
+    headers: HashMap<String, String>,
+    max_tokens: Option<u32>,
+    temperature: Option<f64>,
+    stop: Vec<String>,
+    max_retries: u32,
+    body_template: Option<String>,
+    reasoning_mode: ReasoningMode,
+    agent: ureq::Agent,
+}
+
+impl GeminiDriver {
+    fn endpoint(&self) -> String {
+        format!("{}/models/{}:generateContent", GEMINI_URL, self.model)
+    }
+
+    /// Builds the request body: the configured `body_template`, or the standard Gemini payload
+    fn request_body(&self, messages: &[Message]) -> Result<serde_json::Value> {
+        // Gemini calls the assistant role "model"
+        let contents: Vec<serde_json::Value> = messages.iter().map(|m| {
+            let role = if m.role == "assistant" { "model" } else { "user" };
+            let mut parts = vec![json!({ "text": m.content })];
+            for image in &m.images {
+                parts.push(json!({ "inline_data": { "mime_type": image.mime_type, "data": image.data } }));
+            }
+            json!({ "role": role, "parts": parts })
+        }).collect();
+
+        let body = match &self.body_template {
+            Some(template) => render_body_template(template, &self.model, &self.system_prompt, messages)?,
+            None => {
+                let mut body = json!({
+                    "system_instruction": {
+                        "parts": [{ "text": self.system_prompt }]
+                    },
+                    "contents": contents
+                });
+                if let Some(max_tokens) = self.max_tokens {
+                    body["generationConfig"]["maxOutputTokens"] = json!(max_tokens);
+                }
+                if let Some(temperature) = self.temperature {
+                    body["generationConfig"]["temperature"] = json!(temperature);
+                }
+                if !self.stop.is_empty() {
+                    body["generationConfig"]["stopSequences"] = json!(self.stop);
+                }
+                body
+            },
+        };
+        Ok(body)
+    }
 }
 
 impl LLMService for GeminiDriver {
     fn new(service: &Service, model: &str, system_prompt: &str) -> Result<Self> {
          let api_key = service.api_key.as_deref().context(t!("api_key_required", service = "Gemini"))?;
+         let api_key = resolve_api_key(api_key, "Gemini")?;
          
          Ok(Self {
-             api_key: api_key.to_string(),
+             api_key,
              model: model.to_string(),
              system_prompt: system_prompt.to_string(),
+             query_params: service.query_params.clone().unwrap_or_default(),
+             host_header: service.host_header.clone(),
+             headers: service.headers.clone().unwrap_or_default(),
+             max_tokens: service.max_tokens,
+             temperature: service.temperature,
+             stop: service.stop.clone().unwrap_or_default(),
+             max_retries: service.max_retries.unwrap_or(0),
+             body_template: service.body_template.clone(),
+             reasoning_mode: service.reasoning_mode.unwrap_or_default(),
+             agent: build_agent(service)?,
          })
     }
 
-    fn complete(&self, prompt: &str) -> Result<(String, Option<String>)> {
-        let base_url = "https://generativelanguage.googleapis.com/v1beta";
-        let endpoint = format!("{}/models/{}:generateContent", base_url, self.model);
+    fn complete(&self, messages: &[Message]) -> Result<Completion> {
+        let endpoint = self.endpoint();
+        let body = self.request_body(messages)?;
 
-        let body = json!({
-            "system_instruction": {
-                "parts": [{ "text": self.system_prompt }]
-            },
-            "contents": [{
-                "role": "user",
-                "parts": [{ "text": prompt }]
-            }]
-        });
-
-        let res = ureq::post(&endpoint)
+        let req = with_host_header(with_query_params(self.agent.post(&endpoint), &self.query_params), &self.host_header)
             .set("x-goog-api-key", &self.api_key)
-            .set("Content-Type", "application/json")
-            .send_json(body);
+            .set("Content-Type", "application/json");
+        let req = with_headers(req, &self.headers);
+        let res = send_with_retries(&req, Some(&body), self.max_retries);
 
         match res {
             Ok(response) => {
                 let json: serde_json::Value = response.into_json().context("Failed to parse Gemini response")?;
                 
-                // candidates[0].content.parts[0].text
-                let content = json["candidates"][0]["content"]["parts"][0]["text"]
-                    .as_str()
-                    .map(|s| s.to_string())
+                let parts = json["candidates"][0]["content"]["parts"]
+                    .as_array()
                     .context("Invalid response format from Gemini")?;
-                
-                 if let Some(start) = content.find("<think>") {
-                     if let Some(end) = content.find("</think>") {
-                          let thinking = content[start + 7..end].trim().to_string();
-                          let response_part = content[end + 8..].trim().to_string();
-                          return Ok((response_part, Some(thinking)));
-                     }
+
+                // With thinking enabled, the thought summaries are parts flagged with "thought": true
+                let mut content = String::new();
+                let mut thinking = String::new();
+                for part in parts {
+                    let text = part["text"].as_str().unwrap_or_default();
+                    if part["thought"].as_bool() == Some(true) {
+                        thinking.push_str(text);
+                    } else {
+                        content.push_str(text);
+                    }
                 }
 
-                Ok((content, None))
+                // Native thinking wins; the <think> scan is left for models that write tags in the text
+                let native = if thinking.is_empty() { ReasoningMode::Tags } else { ReasoningMode::Field };
+                let usage = Usage::parse(&json["usageMetadata"], "promptTokenCount", "candidatesTokenCount");
+                let truncated = json["candidates"][0]["finishReason"] == "MAX_TOKENS";
+                Ok(Completion::from(extract_reasoning(self.reasoning_mode, native, content, Some(thinking))).with_usage(usage).with_truncated(truncated))
             },
             Err(ureq::Error::Status(code, response)) => {
                  let text = response.into_string().unwrap_or_default();
                  bail!("Gemini API error: Status: {}, Body: {}", code, text);
             },
-            Err(e) => bail!("Request failed: {}", e),
+            Err(e) => Err(request_failed(e)),
         }
     }
 
+    fn build_request(&self, messages: &[Message]) -> Result<(String, serde_json::Value)> {
+        Ok((url_with_query(&self.agent, &self.endpoint(), &self.query_params), self.request_body(messages)?))
+    }
+
     fn model(&self) -> &str {
         &self.model
     }
@@ -77,13 +142,55 @@ impl LLMService for GeminiDriver {
         &self.system_prompt
     }
 
+    fn default_model() -> Option<&'static str> {
+        Some("gemini-1.5-pro")
+    }
+
+    fn set_max_tokens(&mut self, max_tokens: u32) {
+        self.max_tokens = Some(max_tokens);
+    }
+
+    fn set_temperature(&mut self, temperature: f64) {
+        self.temperature = Some(temperature);
+    }
+
+    fn set_stop(&mut self, stop: &[String]) {
+        self.stop = stop.to_vec();
+    }
+
+    fn append_system_prompt(&mut self, text: &str) {
+        self.system_prompt.push_str(text);
+    }
+
+    fn set_system_prompt(&mut self, text: &str) {
+        self.system_prompt = text.to_string();
+    }
+
+    fn model_info(&self, model: &str) -> Result<serde_json::Value> {
+        let endpoint = format!("{}/models/{}", GEMINI_URL, model.trim_start_matches("models/"));
+
+        let req = with_host_header(with_query_params(self.agent.get(&endpoint), &self.query_params), &self.host_header)
+             .set("x-goog-api-key", &self.api_key);
+        let req = with_headers(req, &self.headers);
+        match send_with_retries(&req, None, self.max_retries) {
+            Ok(response) => response.into_json().context("Failed to parse Gemini model response"),
+            Err(ureq::Error::Status(404, _)) => bail!("{}", t!("model_info_not_found", model = model, service = "Gemini")),
+            Err(ureq::Error::Status(code, response)) => {
+                 let text = response.into_string().unwrap_or_default();
+                 bail!("Gemini API error: Status: {}, Body: {}", code, text)
+            },
+            Err(e) => Err(request_failed(e)),
+        }
+    }
+
     fn list_models(&self) -> Result<Vec<String>> {
-        let base_url = "https://generativelanguage.googleapis.com/v1beta";
+        let base_url = GEMINI_URL;
         let endpoint = format!("{}/models", base_url);
 
-        let res = ureq::get(&endpoint)
-             .set("x-goog-api-key", &self.api_key)
-             .call();
+        let req = with_host_header(with_query_params(self.agent.get(&endpoint), &self.query_params), &self.host_header)
+             .set("x-goog-api-key", &self.api_key);
+        let req = with_headers(req, &self.headers);
+        let res = send_with_retries(&req, None, self.max_retries);
 
         match res {
             Ok(response) => {
@@ -101,9 +208,12 @@ impl LLMService for GeminiDriver {
             },
             Err(ureq::Error::Status(code, response)) => {
                  let text = response.into_string().unwrap_or_default();
-                 bail!("Gemini API error: Status: {}, Body: {}", code, text);
+                 match code {
+                     404 | 405 => bail!("{}", t!("list_models_not_supported", service = "Gemini")),
+                     _ => bail!("Gemini API error: Status: {}, Body: {}", code, text),
+                 }
             },
-            Err(e) => bail!("Request failed: {}", e),
+            Err(e) => Err(request_failed(e)),
         }
     }
 }
@@ -0,0 +1,74 @@
+use anyhow::Result;
+use crate::config::{Message, Service};
+use std::io::Write;
+use super::{Completion, LLMService, MISTRAL_URL, openai::OpenAIDriver};
+
+/// Mistral's La Plateforme. Its chat and model listing endpoints follow OpenAI's, so requests
+/// and responses are handled by the OpenAI driver.
+pub struct MistralDriver {
+    inner: OpenAIDriver,
+}
+
+impl LLMService for MistralDriver {
+    fn new(service: &Service, model: &str, system_prompt: &str) -> Result<Self> {
+        Ok(Self {
+            inner: OpenAIDriver::compatible(service, model, system_prompt, "Mistral", MISTRAL_URL)?,
+        })
+    }
+
+    fn complete(&self, messages: &[Message]) -> Result<Completion> {
+        self.inner.complete(messages)
+    }
+
+    fn complete_streaming(&self, messages: &[Message], sink: &mut dyn Write) -> Result<Completion> {
+        self.inner.complete_streaming(messages, sink)
+    }
+
+    fn build_request(&self, messages: &[Message]) -> Result<(String, serde_json::Value)> {
+        self.inner.build_request(messages)
+    }
+
+    fn model(&self) -> &str {
+        self.inner.model()
+    }
+
+    fn system_prompt(&self) -> &str {
+        self.inner.system_prompt()
+    }
+
+    fn default_model() -> Option<&'static str> {
+        Some("mistral-large-latest")
+    }
+
+    fn set_max_tokens(&mut self, max_tokens: u32) {
+        self.inner.set_max_tokens(max_tokens);
+    }
+
+    fn set_temperature(&mut self, temperature: f64) {
+        self.inner.set_temperature(temperature);
+    }
+
+    fn set_stop(&mut self, stop: &[String]) {
+        self.inner.set_stop(stop);
+    }
+
+    fn append_system_prompt(&mut self, text: &str) {
+        self.inner.append_system_prompt(text);
+    }
+
+    fn set_system_prompt(&mut self, text: &str) {
+        self.inner.set_system_prompt(text);
+    }
+
+    fn set_response_schema(&mut self, schema: &serde_json::Value) -> bool {
+        self.inner.set_response_schema(schema)
+    }
+
+    fn model_info(&self, model: &str) -> Result<serde_json::Value> {
+        self.inner.model_info(model)
+    }
+
+    fn list_models(&self) -> Result<Vec<String>> {
+        self.inner.list_models()
+    }
+}
@@ -1,15 +1,652 @@
-use crate::config::Service;
-use anyhow::Result;
+use crate::config::{Message, ModelPrice, ReasoningMode, Service};
+use anyhow::{Context, Result, bail};
+use regex::Regex;
+use rust_i18n::t;
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use std::io::{BufRead, BufReader, Write};
+use std::sync::{Arc, Mutex, OnceLock};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::time::Duration;
 
-pub trait LLMService {
+pub const VALID_CLASSES: &[&str] = &["openai", "ollama", "gemini", "anthropic", "mistral", "azure", "openrouter"];
+
+pub const OPENAI_DEFAULT_URL: &str = "https://api.openai.com";
+pub const OLLAMA_DEFAULT_URL: &str = "http://localhost:11434";
+pub const GEMINI_URL: &str = "https://generativelanguage.googleapis.com/v1beta";
+pub const ANTHROPIC_URL: &str = "https://api.anthropic.com";
+pub const MISTRAL_URL: &str = "https://api.mistral.ai";
+pub const OPENROUTER_URL: &str = "https://openrouter.ai/api";
+
+/// Seconds a request may wait to connect, or for the next part of the answer, when the service sets no `timeout`
+pub const DEFAULT_TIMEOUT: u64 = 120;
+
+/// The base URL a service talks to: its `url`, or the class default when unset.
+/// Gemini and Anthropic ignore `url` and always use their own.
+pub fn effective_url(service: &Service) -> Option<&str> {
+    match service.class.as_str() {
+        "openai" => Some(service.url.as_deref().unwrap_or(OPENAI_DEFAULT_URL)),
+        "ollama" => Some(service.url.as_deref().unwrap_or(OLLAMA_DEFAULT_URL)),
+        "gemini" => Some(GEMINI_URL),
+        "anthropic" => Some(ANTHROPIC_URL),
+        "mistral" => Some(service.url.as_deref().unwrap_or(MISTRAL_URL)),
+        "azure" => service.url.as_deref(),
+        "openrouter" => Some(service.url.as_deref().unwrap_or(OPENROUTER_URL)),
+        _ => None,
+    }
+}
+
+/// The built-in default model of a class, if it has one
+pub fn default_model(class: &str) -> Option<&'static str> {
+    match class {
+        "openai" => openai::OpenAIDriver::default_model(),
+        "ollama" => ollama::OllamaDriver::default_model(),
+        "gemini" => gemini::GeminiDriver::default_model(),
+        "anthropic" => anthropic::AnthropicDriver::default_model(),
+        "mistral" => mistral::MistralDriver::default_model(),
+        "openrouter" => openrouter::OpenRouterDriver::default_model(),
+        _ => None,
+    }
+}
+
+/// Whether services of the given class can't work without an `api_key`
+pub fn requires_api_key(class: &str) -> bool {
+    VALID_CLASSES.contains(&class) && class != "ollama"
+}
+
+/// Tokens a request consumed, as reported by the service
+#[derive(Debug, Clone, Copy, Default, PartialEq, Serialize, Deserialize)]
+pub struct Usage {
+    pub input: u64,
+    pub output: u64,
+    /// Prompt tokens served from the provider's prompt cache
+    #[serde(default)]
+    pub cache_read: u64,
+    /// Prompt tokens stored in the provider's prompt cache
+    #[serde(default)]
+    pub cache_write: u64,
+}
+
+impl Usage {
+    /// Reads the token counts from the `input` and `output` fields of a provider's usage object.
+    /// Returns None when the service reported neither.
+    pub fn parse(usage: &serde_json::Value, input: &str, output: &str) -> Option<Self> {
+        let (input, output) = (usage[input].as_u64(), usage[output].as_u64());
+        if input.is_none() && output.is_none() {
+            return None;
+        }
+        Some(Self { input: input.unwrap_or(0), output: output.unwrap_or(0), ..Self::default() })
+    }
+
+    /// Adds the prompt cache counts of a provider's usage object, found at the JSON pointers `read`
+    /// (tokens served from the cache) and `write` (tokens stored in it, for providers that say)
+    pub fn with_cache(self, usage: &serde_json::Value, read: &str, write: Option<&str>) -> Self {
+        let count = |pointer: &str| usage.pointer(pointer).and_then(serde_json::Value::as_u64).unwrap_or(0);
+        Self { cache_read: count(read), cache_write: write.map(count).unwrap_or(0), ..self }
+    }
+
+    pub fn total(&self) -> u64 {
+        self.input + self.output
+    }
+}
+
+impl std::ops::AddAssign for Usage {
+    fn add_assign(&mut self, other: Self) {
+        self.input += other.input;
+        self.output += other.output;
+        self.cache_read += other.cache_read;
+        self.cache_write += other.cache_write;
+    }
+}
+
+/// A model's answer
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct Completion {
+    pub text: String,
+    /// Reasoning that came with the answer, if any
+    pub thinking: Option<String>,
+    /// Tokens spent, when the service reports them
+    pub usage: Option<Usage>,
+    /// Whether the answer was cut off by the length cap (`max_tokens` or the model's own)
+    #[serde(default)]
+    pub truncated: bool,
+}
+
+impl Completion {
+    pub fn with_usage(self, usage: Option<Usage>) -> Self {
+        Self { usage, ..self }
+    }
+
+    pub fn with_truncated(self, truncated: bool) -> Self {
+        Self { truncated, ..self }
+    }
+}
+
+impl From<(String, Option<String>)> for Completion {
+    fn from((text, thinking): (String, Option<String>)) -> Self {
+        Self { text, thinking, usage: None, truncated: false }
+    }
+}
+
+/// A service class. Drivers are shared between threads by --count --parallel, hence Send + Sync.
+pub trait LLMService: Send + Sync {
     fn new(service: &Service, model: &str, system_prompt: &str) -> Result<Self> where Self: Sized;
-    fn complete(&self, prompt: &str) -> Result<(String, Option<String>)>;
+    fn complete(&self, messages: &[Message]) -> Result<Completion>;
+    /// The endpoint (with query parameters) and body `complete` would send, for --dry-run
+    fn build_request(&self, messages: &[Message]) -> Result<(String, serde_json::Value)>;
     fn model(&self) -> &str;
     fn system_prompt(&self) -> &str;
     fn list_models(&self) -> Result<Vec<String>>;
+    /// Like `list_models`, with each model's price when the API publishes one
+    fn list_priced_models(&self) -> Result<Vec<(String, Option<ModelPrice>)>> {
+        Ok(self.list_models()?.into_iter().map(|model| (model, None)).collect())
+    }
+    /// What the API says about `model` (context length, capabilities...), as it says it
+    fn model_info(&self, model: &str) -> Result<serde_json::Value>;
+    /// Caps the length of the answer, in tokens. Ignored when the service uses a `body_template`
+    fn set_max_tokens(&mut self, max_tokens: u32);
+    /// Sets the sampling temperature. Ignored when the service uses a `body_template`
+    fn set_temperature(&mut self, temperature: f64);
+    /// Sets the sequences that end the answer. Ignored when the service uses a `body_template`
+    fn set_stop(&mut self, stop: &[String]);
+    /// Adds text at the end of the system prompt
+    fn append_system_prompt(&mut self, text: &str);
+    /// Replaces the system prompt
+    fn set_system_prompt(&mut self, text: &str);
+
+    /// Like `complete`, but writes the answer (without the reasoning) to `sink` as it arrives.
+    /// Drivers that can't stream write the whole answer once it's complete.
+    fn complete_streaming(&self, messages: &[Message], sink: &mut dyn Write) -> Result<Completion> {
+        let completion = self.complete(messages)?;
+        sink.write_all(completion.text.as_bytes())?;
+        sink.flush()?;
+        Ok(completion)
+    }
+
+    /// Model used when neither the command line nor the service configuration names one
+    fn default_model() -> Option<&'static str> where Self: Sized {
+        None
+    }
+
+    /// Whether the API continues a trailing assistant message instead of answering anew
+    fn supports_prefill(&self) -> bool {
+        false
+    }
+
+    /// Asks the API to cache the system prompt between requests. Returns false if the service can't.
+    fn enable_prompt_caching(&mut self) -> bool {
+        false
+    }
+
+    /// Asks the API for an answer that is JSON matching `schema`. Returns false if the service can't.
+    fn set_response_schema(&mut self, _schema: &serde_json::Value) -> bool {
+        false
+    }
+}
+
+/// When set, setup problems that are normally warnings (e.g. placeholder API keys) become errors
+static STRICT: AtomicBool = AtomicBool::new(false);
+
+pub fn set_strict(strict: bool) {
+    STRICT.store(strict, Ordering::Relaxed);
+}
+
+/// When set, every request and response is printed on stderr, with the API keys masked
+static VERBOSE: AtomicBool = AtomicBool::new(false);
+
+pub fn set_verbose(verbose: bool) {
+    VERBOSE.store(verbose, Ordering::Relaxed);
+}
+
+pub fn is_verbose() -> bool {
+    VERBOSE.load(Ordering::Relaxed)
+}
+
+/// Headers carrying credentials, shown with only their last 4 characters
+const SECRET_HEADERS: &[&str] = &["authorization", "x-api-key", "x-goog-api-key", "api-key"];
+
+fn mask_secret(value: &str) -> String {
+    let (scheme, secret) = value.split_once(' ').unwrap_or(("", value));
+    let visible: String = secret.chars().rev().take(4).collect::<Vec<_>>().into_iter().rev().collect();
+    let masked = format!("****{}", if secret.chars().count() > 8 { visible.as_str() } else { "" });
+    if scheme.is_empty() { masked } else { format!("{} {}", scheme, masked) }
+}
+
+fn log_request(req: &ureq::Request, body: Option<&serde_json::Value>) {
+    eprintln!("> {} {}", req.method(), req.url());
+    for name in req.header_names() {
+        let value = req.header(&name).unwrap_or_default();
+        if SECRET_HEADERS.contains(&name.to_lowercase().as_str()) {
+            eprintln!("> {}: {}", name, mask_secret(value));
+        } else {
+            eprintln!("> {}: {}", name, value);
+        }
+    }
+    if let Some(body) = body {
+        eprintln!("> {}", serde_json::to_string_pretty(body).unwrap_or_else(|_| body.to_string()));
+    }
+}
+
+/// Prints the status and body of a response. Reading the body consumes it, so the response is
+/// rebuilt from what was read. Streamed bodies are left alone, to be printed as they arrive.
+#[allow(clippy::result_large_err)]
+fn log_response(result: Result<ureq::Response, ureq::Error>) -> Result<ureq::Response, ureq::Error> {
+    let (response, failed) = match result {
+        Ok(response) => (response, false),
+        Err(ureq::Error::Status(_, response)) => (response, true),
+        Err(err) => {
+            eprintln!("< {}", err);
+            return Err(err);
+        },
+    };
+    eprintln!("< {} {}", response.status(), response.status_text());
+    if matches!(response.content_type(), "text/event-stream" | "application/x-ndjson") {
+        eprintln!("< {}", t!("verbose_streamed_body"));
+        return if failed { Err(ureq::Error::Status(response.status(), response)) } else { Ok(response) };
+    }
+
+    // Length and encoding describe the body as it came over the wire, not the decoded text
+    let mut head = format!("{} {} {}\r\n", response.http_version(), response.status(), response.status_text());
+    for name in response.headers_names() {
+        if !matches!(name.to_lowercase().as_str(), "content-length" | "content-encoding" | "transfer-encoding") {
+            for value in response.all(&name) {
+                head.push_str(&format!("{}: {}\r\n", name, value));
+            }
+        }
+    }
+    let status = response.status();
+    let text = response.into_string().map_err(ureq::Error::from)?;
+    eprintln!("< {}", text);
+    let rebuilt: ureq::Response = format!("{}\r\n{}", head, text).parse()?;
+    if failed { Err(ureq::Error::Status(status, rebuilt)) } else { Ok(rebuilt) }
+}
+
+/// Detects obvious placeholder API keys copied from example configs
+pub fn looks_like_placeholder(key: &str) -> bool {
+    const MARKERS: &[&str] = &["your", "xxxx", "changeme", "change_me", "change-me", "placeholder", "..."];
+    let key = key.trim().to_lowercase();
+    key.is_empty() || MARKERS.iter().any(|marker| key.contains(marker))
+}
+
+/// Resolves the configured API key: "file:<path>" reads the key from that file (trimmed).
+/// The result is then checked with `check_api_key`.
+pub fn resolve_api_key(key: &str, service: &str) -> Result<String> {
+    let key = match key.strip_prefix("file:") {
+        Some(path) => std::fs::read_to_string(path)
+            .with_context(|| t!("api_key_file_unreadable", service = service, path = path))?
+            .trim()
+            .to_string(),
+        None => key.to_string(),
+    };
+    check_api_key(&key, service)?;
+    Ok(key)
+}
+
+/// Warns (or fails in strict mode) when an API key is clearly a placeholder
+fn check_api_key(key: &str, service: &str) -> Result<()> {
+    if looks_like_placeholder(key) {
+        if STRICT.load(Ordering::Relaxed) {
+            bail!("{}", t!("api_key_placeholder", service = service));
+        }
+        eprintln!("{}", t!("api_key_placeholder_warning", service = service));
+    }
+    Ok(())
+}
+
+/// Builds the HTTP agent for a service's requests, applying its TLS settings.
+/// Services with the same connection settings share one agent, and so its pooled connections.
+pub fn build_agent(service: &Service) -> Result<ureq::Agent> {
+    static AGENTS: OnceLock<Mutex<HashMap<AgentKey, ureq::Agent>>> = OnceLock::new();
+
+    let key = AgentKey {
+        ca_cert: service.ca_cert.clone(),
+        danger_accept_invalid_certs: service.danger_accept_invalid_certs == Some(true),
+        timeout: service.timeout.unwrap_or(DEFAULT_TIMEOUT),
+    };
+    let mut agents = AGENTS.get_or_init(Default::default).lock().unwrap_or_else(|e| e.into_inner());
+    if let Some(agent) = agents.get(&key) {
+        return Ok(agent.clone());
+    }
+
+    let mut builder = ureq::AgentBuilder::new()
+        .timeout_connect(Duration::from_secs(key.timeout))
+        .timeout_read(Duration::from_secs(key.timeout));
+    if key.ca_cert.is_some() || key.danger_accept_invalid_certs {
+        if key.danger_accept_invalid_certs {
+            eprintln!("{}", t!("tls_verification_disabled"));
+        }
+        builder = builder.tls_config(Arc::new(tls::client_config(service)?));
+    }
+    let agent = builder.build();
+    agents.insert(key, agent.clone());
+    Ok(agent)
+}
+
+/// Everything in a service's configuration that changes how its agent connects
+#[derive(PartialEq, Eq, Hash)]
+struct AgentKey {
+    ca_cert: Option<String>,
+    danger_accept_invalid_certs: bool,
+    timeout: u64,
+}
+
+/// A request that got no answer within the service's `timeout`
+#[derive(Debug)]
+pub struct RequestTimedOut;
+
+impl std::fmt::Display for RequestTimedOut {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "Request timed out")
+    }
+}
+
+impl std::error::Error for RequestTimedOut {}
+
+/// Error for a request that got no HTTP response. Timeouts become `RequestTimedOut`, so the
+/// client can report them with the service name and the limit.
+pub fn request_failed(err: ureq::Error) -> anyhow::Error {
+    if let ureq::Error::Transport(transport) = &err
+        && is_timeout(transport) {
+            return RequestTimedOut.into();
+    }
+    anyhow::anyhow!("Request failed: {}", err)
+}
+
+/// Statuses that usually clear up on their own: rate limits and overloaded or restarting servers
+const RETRY_STATUSES: &[u16] = &[429, 500, 502, 503, 504];
+
+/// Longest wait a `Retry-After` header can ask for; a service wanting more gets no retry
+const MAX_RETRY_WAIT: u64 = 60;
+
+/// Seconds a `Retry-After` header asks to wait: either a number of seconds or an HTTP date
+fn retry_after(value: &str) -> Option<u64> {
+    let value = value.trim();
+    if let Ok(seconds) = value.parse() {
+        return Some(seconds);
+    }
+    let now = std::time::SystemTime::now().duration_since(std::time::UNIX_EPOCH).ok()?.as_secs();
+    Some(parse_http_date(value)?.saturating_sub(now))
+}
+
+/// Unix time of a date in the HTTP format, e.g. "Wed, 21 Oct 2015 07:28:00 GMT"
+fn parse_http_date(value: &str) -> Option<u64> {
+    const MONTHS: [&str; 12] = ["Jan", "Feb", "Mar", "Apr", "May", "Jun", "Jul", "Aug", "Sep", "Oct", "Nov", "Dec"];
+    let mut parts = value.split_whitespace().skip(1);
+    let day: u64 = parts.next()?.parse().ok()?;
+    let month = parts.next()?;
+    let month = MONTHS.iter().position(|name| *name == month)? as u64 + 1;
+    let year: u64 = parts.next()?.parse().ok()?;
+    let mut time = parts.next()?.split(':').map(|part| part.parse::<u64>().ok());
+    let (hours, minutes, seconds) = (time.next()??, time.next()??, time.next()??);
+    if parts.next()? != "GMT" || !(1..=31).contains(&day) {
+        return None;
+    }
+    // Days since 1970-01-01, counting years from March so leap days fall at their end
+    let (year, month) = if month <= 2 { (year - 1, month + 9) } else { (year, month - 3) };
+    let (era, year_of_era) = (year / 400, year % 400);
+    let day_of_year = (153 * month + 2) / 5 + day - 1;
+    let day_of_era = year_of_era * 365 + year_of_era / 4 - year_of_era / 100 + day_of_year;
+    let days = (era * 146_097 + day_of_era).checked_sub(719_468)?;
+    Some(days * 86_400 + hours * 3_600 + minutes * 60 + seconds)
+}
+
+/// Sends `req` (posting `body` when given), trying again up to `max_retries` times while the service
+/// answers 429 or a 5xx status. Waits 1s, 2s, 4s... between attempts, or what the `Retry-After` header
+/// asks for, giving up when that is more than `MAX_RETRY_WAIT`. The last result is returned untouched,
+/// so each driver reports errors as usual.
+#[allow(clippy::result_large_err)]
+pub fn send_with_retries(req: &ureq::Request, body: Option<&serde_json::Value>, max_retries: u32) -> Result<ureq::Response, ureq::Error> {
+    let verbose = VERBOSE.load(Ordering::Relaxed);
+    let mut attempt = 0;
+    loop {
+        if verbose {
+            log_request(req, body);
+        }
+        let result = match body {
+            Some(body) => req.clone().send_json(body),
+            None => req.clone().call(),
+        };
+        let result = if verbose { log_response(result) } else { result };
+        match result {
+            Err(ureq::Error::Status(code, response)) if attempt < max_retries && RETRY_STATUSES.contains(&code) => {
+                attempt += 1;
+                let wait = match response.header("Retry-After").and_then(retry_after) {
+                    Some(wait) if wait > MAX_RETRY_WAIT => {
+                        eprintln!("{}", t!("request_retry_too_long", status = code, seconds = wait, max = MAX_RETRY_WAIT));
+                        return Err(ureq::Error::Status(code, response));
+                    },
+                    Some(wait) => wait,
+                    None => 2u64.saturating_pow(attempt - 1),
+                };
+                eprintln!("{}", t!("request_retry", status = code, seconds = wait, attempt = attempt, retries = max_retries));
+                std::thread::sleep(Duration::from_secs(wait));
+            }
+            result => return result,
+        }
+    }
+}
+
+/// Whether an error (or what caused it) is an I/O timeout
+pub fn is_timeout(err: &(dyn std::error::Error + 'static)) -> bool {
+    let mut cause = Some(err);
+    while let Some(err) = cause {
+        if err.is::<RequestTimedOut>()
+            || err.downcast_ref::<std::io::Error>().is_some_and(|e| matches!(e.kind(), std::io::ErrorKind::TimedOut | std::io::ErrorKind::WouldBlock)) {
+                return true;
+        }
+        cause = err.source();
+    }
+    false
+}
+
+/// Some gateways answer with server-sent events even when streaming wasn't requested
+pub fn is_event_stream(response: &ureq::Response) -> bool {
+    response.content_type() == "text/event-stream"
+}
+
+/// Collects the JSON payloads of the `data:` lines of a server-sent events body, up to `[DONE]`
+pub fn read_event_stream(response: ureq::Response) -> Result<Vec<serde_json::Value>> {
+    let mut chunks = Vec::new();
+    for_each_stream_chunk(response, |chunk| {
+        chunks.push(chunk.clone());
+        Ok(())
+    })?;
+    Ok(chunks)
+}
+
+/// Calls `handle` with each JSON payload of a streamed body as soon as it arrives. Understands
+/// server-sent events (`data:` lines, up to `[DONE]`) and newline-delimited JSON.
+pub fn for_each_stream_chunk(response: ureq::Response, mut handle: impl FnMut(&serde_json::Value) -> Result<()>) -> Result<()> {
+    for line in BufReader::new(response.into_reader()).lines() {
+        let line = line?;
+        let data = line.strip_prefix("data:").unwrap_or(&line).trim();
+        if data == "[DONE]" {
+            break;
+        }
+        // Blank lines, comments and other event fields carry no payload
+        if !data.starts_with('{') {
+            continue;
+        }
+        handle(&serde_json::from_str(data).context("Failed to parse event stream chunk")?)?;
+    }
+    Ok(())
+}
+
+/// Passes a streamed answer on to a sink as it arrives. A leading <think> block is held back
+/// until its closing tag shows up, so the reasoning never reaches the sink. At the end, the
+/// whole text goes through `extract_reasoning`, giving the same result as a non-streamed answer.
+pub struct StreamWriter<'s> {
+    sink: &'s mut dyn Write,
+    mode: ReasoningMode,
+    native: ReasoningMode,
+    content: String,
+    reasoning: String,
+    /// Bytes of `content` already written or skipped
+    written: usize,
+    /// Whether a leading <think> block may still be arriving
+    pending_think: bool,
+    /// Token counts, if a chunk reported them
+    usage: Option<Usage>,
+    /// Whether whitespace after the <think> block is still being skipped
+    skip_whitespace: bool,
+    /// Whether a chunk said the answer was cut off by the length cap
+    truncated: bool,
+}
+
+impl<'s> StreamWriter<'s> {
+    pub fn new(sink: &'s mut dyn Write, mode: ReasoningMode, native: ReasoningMode) -> Self {
+        let pending_think = mode == ReasoningMode::Tags || (mode == ReasoningMode::Auto && native == ReasoningMode::Tags);
+        Self { sink, mode, native, content: String::new(), reasoning: String::new(), written: 0, pending_think, usage: None, skip_whitespace: false, truncated: false }
+    }
+
+    /// Adds a piece of the answer and/or of the reasoning the API sends in a field of its own
+    pub fn push(&mut self, content: Option<&str>, reasoning: Option<&str>) -> Result<()> {
+        if let Some(reasoning) = reasoning {
+            self.reasoning.push_str(reasoning);
+        }
+        let Some(content) = content else {
+            return Ok(());
+        };
+        self.content.push_str(content);
+
+        if self.pending_think {
+            let start = self.content.trim_start();
+            if start.starts_with("<think>") {
+                let Some(end) = self.content.find("</think>") else {
+                    return Ok(());
+                };
+                self.written = end + "</think>".len();
+                self.skip_whitespace = true;
+            } else if "<think>".starts_with(start) {
+                // Too short to tell yet
+                return Ok(());
+            }
+            self.pending_think = false;
+        }
+        self.write_pending()
+    }
+
+    fn write_pending(&mut self) -> Result<()> {
+        let mut pending = &self.content[self.written..];
+        if self.skip_whitespace {
+            let trimmed = pending.trim_start();
+            self.written += pending.len() - trimmed.len();
+            self.skip_whitespace = trimmed.is_empty();
+            pending = trimmed;
+        }
+        if !pending.is_empty() {
+            self.sink.write_all(pending.as_bytes())?;
+            self.sink.flush()?;
+            self.written = self.content.len();
+        }
+        Ok(())
+    }
+
+    /// Keeps the token counts reported by a chunk (usually the last one)
+    pub fn set_usage(&mut self, usage: Option<Usage>) {
+        if usage.is_some() {
+            self.usage = usage;
+        }
+    }
+
+    /// Notes that the answer was cut off, when the chunk holding the finish reason says so
+    pub fn set_truncated(&mut self, truncated: bool) {
+        self.truncated |= truncated;
+    }
+
+    /// Writes whatever was still held back (e.g. a <think> block that never closed) and
+    /// returns the answer and the reasoning
+    pub fn finish(mut self) -> Result<Completion> {
+        self.pending_think = false;
+        self.write_pending()?;
+        let reasoning = Some(self.reasoning).filter(|r| !r.is_empty());
+        Ok(Completion::from(extract_reasoning(self.mode, self.native, self.content, reasoning)).with_usage(self.usage).with_truncated(self.truncated))
+    }
+}
+
+/// Fills a service's `body_template`. `{{model}}`, `{{system}}` and `{{prompt}}` become JSON strings
+/// (quotes included) and `{{messages}}` the array of chat turns, so the result must parse as JSON.
+pub fn render_body_template(template: &str, model: &str, system_prompt: &str, messages: &[Message]) -> Result<serde_json::Value> {
+    let prompt = messages.iter().rev().find(|m| m.role == "user").map(|m| m.content.as_str()).unwrap_or("");
+    let values = [
+        ("model", serde_json::to_string(model)?),
+        ("system", serde_json::to_string(system_prompt)?),
+        ("prompt", serde_json::to_string(prompt)?),
+        ("messages", serde_json::to_string(messages)?),
+    ];
+    // In one pass, so a placeholder inside a prompt or message is left as written
+    let placeholder = Regex::new(r"\{\{(model|system|prompt|messages)\}\}").unwrap();
+    let body = placeholder.replace_all(template, |caps: &regex::Captures| {
+        values.iter().find(|(name, _)| *name == &caps[1]).map(|(_, value)| value.clone()).unwrap_or_default()
+    });
+    serde_json::from_str(&body).context(t!("body_template_invalid"))
 }
 
+/// Splits the reasoning from the answer according to the service's `reasoning_mode`.
+/// `native` is what `auto` means for the class; `field` is the reasoning the API returned
+/// in a field of its own, if any.
+pub fn extract_reasoning(mode: ReasoningMode, native: ReasoningMode, content: String, field: Option<String>) -> (String, Option<String>) {
+    let mode = if mode == ReasoningMode::Auto { native } else { mode };
+    match mode {
+        ReasoningMode::Tags => {
+            if let Some(start) = content.find("<think>")
+                && let Some(end) = content.find("</think>") {
+                    let thinking = content[start + 7..end].trim().to_string();
+                    let response_part = content[end + 8..].trim().to_string();
+                    return (response_part, Some(thinking));
+            }
+            (content, None)
+        },
+        ReasoningMode::Field => (content, field.filter(|f| !f.is_empty())),
+        ReasoningMode::None | ReasoningMode::Auto => (content, None),
+    }
+}
+
+/// Joins a base URL and an endpoint path, tolerating a slash on either side.
+pub fn join_url(base_url: &str, path: &str) -> String {
+    format!("{}/{}", base_url.trim_end_matches('/'), path.trim_start_matches('/'))
+}
+
+/// Appends the service's extra query parameters to a request. ureq takes care of URL-encoding.
+pub fn with_query_params(mut req: ureq::Request, params: &HashMap<String, String>) -> ureq::Request {
+    for (key, value) in params {
+        req = req.query(key, value);
+    }
+    req
+}
+
+/// Full URL of a request to `endpoint`, with the service's query parameters
+pub fn url_with_query(agent: &ureq::Agent, endpoint: &str, params: &HashMap<String, String>) -> String {
+    with_query_params(agent.post(endpoint), params).request_url()
+        .map(|url| url.as_url().to_string())
+        .unwrap_or_else(|_| endpoint.to_string())
+}
+
+/// Adds the service's extra `headers`. Headers the driver already set (authentication, content type)
+/// are kept, so a gateway header can't break the request.
+pub fn with_headers(mut req: ureq::Request, headers: &HashMap<String, String>) -> ureq::Request {
+    for (name, value) in headers {
+        if !req.has(name) {
+            req = req.set(name, value);
+        }
+    }
+    req
+}
+
+/// Sends the service's `host_header` instead of the Host taken from the URL. TLS SNI still uses the URL's host.
+pub fn with_host_header(req: ureq::Request, host_header: &Option<String>) -> ureq::Request {
+    match host_header {
+        Some(host) => req.set("Host", host),
+        None => req,
+    }
+}
+
+mod tls;
+
 pub mod openai;
 pub mod ollama;
 pub mod gemini;
 pub mod anthropic;
+pub mod mistral;
+pub mod azure;
+pub mod openrouter;
@@ -1,20 +1,141 @@
 use anyhow::{Result, bail, Context};
 use serde_json::json;
 use rust_i18n::t;
-use crate::config::Service;
-use super::LLMService;
+use crate::config::{Message, ReasoningMode, Service};
+use std::collections::HashMap;
+use std::io::Write;
+use super::{Completion, LLMService, Usage, OLLAMA_DEFAULT_URL, build_agent, extract_reasoning, for_each_stream_chunk, is_event_stream, join_url, read_event_stream, render_body_template, request_failed, resolve_api_key, send_with_retries, url_with_query, with_headers, with_host_header, with_query_params, StreamWriter};
 
 pub struct OllamaDriver {
     url: String,
     model: String,
     system_prompt: String,
     api_key: Option<String>,
+    query_params: HashMap<String, String>,
+    host_header: Option<String>,
+    headers: HashMap<String, String>,
+    max_tokens: Option<u32>,
+    temperature: Option<f64>,
+    stop: Vec<String>,
+    keep_alive: Option<serde_json::Value>,
+    options: HashMap<String, serde_json::Value>,
+    response_schema: Option<serde_json::Value>,
+    max_retries: u32,
+    body_template: Option<String>,
+    reasoning_mode: ReasoningMode,
+    completions_path: String,
+    models_path: String,
+    agent: ureq::Agent,
+}
+
+impl OllamaDriver {
+    /// Chat request body; `stream` asks for the answer as newline-delimited JSON
+    fn request_body(&self, messages: &[Message], stream: bool) -> Result<serde_json::Value> {
+        let mut chat = vec![json!({"role": "system", "content": self.system_prompt})];
+        for message in messages {
+            let mut entry = json!({"role": message.role, "content": message.content});
+            if !message.images.is_empty() {
+                entry["images"] = json!(message.images.iter().map(|image| &image.data).collect::<Vec<_>>());
+            }
+            chat.push(entry);
+        }
+        
+        let body = match &self.body_template {
+            Some(template) => render_body_template(template, &self.model, &self.system_prompt, messages)?,
+            None => {
+                let mut body = json!({
+                    "model": self.model,
+                    "messages": chat,
+                    "stream": stream
+                });
+                if let Some(keep_alive) = &self.keep_alive {
+                    body["keep_alive"] = keep_alive.clone();
+                }
+                // The settings askme has names for go last, so they win over the same option
+                for (option, value) in &self.options {
+                    body["options"][option] = value.clone();
+                }
+                if let Some(max_tokens) = self.max_tokens {
+                    body["options"]["num_predict"] = json!(max_tokens);
+                }
+                if let Some(temperature) = self.temperature {
+                    body["options"]["temperature"] = json!(temperature);
+                }
+                if !self.stop.is_empty() {
+                    body["options"]["stop"] = json!(self.stop);
+                }
+                if let Some(schema) = &self.response_schema {
+                    body["format"] = schema.clone();
+                }
+                body
+            },
+        };
+        Ok(body)
+    }
+
+    /// Posts a chat request, turning API errors into readable messages
+    fn send(&self, body: serde_json::Value) -> Result<ureq::Response> {
+        let endpoint = join_url(&self.url, &self.completions_path);
+
+        let mut req = with_host_header(with_query_params(self.agent.post(&endpoint), &self.query_params), &self.host_header);
+        
+        if let Some(key) = &self.api_key {
+            req = req.set("Authorization", &format!("Bearer {}", key));
+        }
+
+        let req = with_headers(req, &self.headers);
+        match send_with_retries(&req, Some(&body), self.max_retries) {
+            Ok(response) => Ok(response),
+            Err(ureq::Error::Status(code, response)) => {
+                 let text = response.into_string().unwrap_or_default();
+                 match code {
+                     404 => bail!("{}", t!("api_error_not_found")),
+                     _ => bail!("Ollama API error: Status: {}, Body: {}", code, text),
+                 }
+            },
+            Err(e) => Err(request_failed(e)),
+        }
+    }
+
+    /// Reads a whole (not streamed on request) chat response
+    fn parse_response(&self, response: ureq::Response) -> Result<Completion> {
+        if is_event_stream(&response) {
+            let chunks = read_event_stream(response)?;
+            let response_text: String = chunks.iter().filter_map(|c| c["message"]["content"].as_str()).collect();
+            let thinking: String = chunks.iter().filter_map(|c| c["message"]["thinking"].as_str()).collect();
+            let usage = chunks.iter().rev().find_map(|c| Usage::parse(c, "prompt_eval_count", "eval_count"));
+            let truncated = chunks.iter().any(|c| c["done_reason"] == "length");
+            return Ok(Completion::from(extract_reasoning(self.reasoning_mode, ReasoningMode::Field, response_text, Some(thinking))).with_usage(usage).with_truncated(truncated));
+        }
+
+        let json: serde_json::Value = response.into_json().context("Failed to parse Ollama response")?;
+        let response_text = json["message"]["content"]
+           .as_str()
+           .map(|s| s.to_string())
+           .context("Invalid response format from Ollama")?;
+        
+        // Extract thinking if present
+        // Note: Ollama might return it in a different way depending on model or custom fields?
+        // User said: "chain of reasoning comes in the 'thinking' field of the response"
+        // This implies it's a top-level field or inside the message object?
+        // Usually for chat API it's inside message object? Or maybe for /api/generate it is separate?
+        // User said "thinking" field. Let's assume top level or message level.
+        // Let's check both for robustness.
+        let thinking = json.get("thinking")
+            .or_else(|| json["message"].get("thinking"))
+            .and_then(|t| t.as_str())
+            .map(|s| s.to_string());
+            
+        let usage = Usage::parse(&json, "prompt_eval_count", "eval_count");
+        let truncated = json["done_reason"] == "length";
+        Ok(Completion::from(extract_reasoning(self.reasoning_mode, ReasoningMode::Field, response_text, thinking)).with_usage(usage).with_truncated(truncated))
+    }
 }
 
 impl LLMService for OllamaDriver {
     fn new(service: &Service, model: &str, system_prompt: &str) -> Result<Self> {
-         let url = service.url.as_deref().unwrap_or("http://localhost:11434");
-         let api_key = service.api_key.as_deref();
+         let url = service.url.as_deref().unwrap_or(OLLAMA_DEFAULT_URL);
+         let api_key = service.api_key.as_deref().map(|key| resolve_api_key(key, "Ollama")).transpose()?;
          
          if system_prompt.is_empty() {
               bail!("{}", t!("system_prompt_required", service = "Ollama"));
@@ -24,62 +145,52 @@ impl LLMService for OllamaDriver {
              url: url.to_string(),
              model: model.to_string(),
              system_prompt: system_prompt.to_string(),
-             api_key: api_key.map(|s| s.to_string()),
+             api_key,
+             query_params: service.query_params.clone().unwrap_or_default(),
+             host_header: service.host_header.clone(),
+             headers: service.headers.clone().unwrap_or_default(),
+             max_tokens: service.max_tokens,
+             temperature: service.temperature,
+             stop: service.stop.clone().unwrap_or_default(),
+             keep_alive: service.keep_alive.clone(),
+             options: service.options.clone().unwrap_or_default(),
+             response_schema: None,
+             max_retries: service.max_retries.unwrap_or(0),
+             body_template: service.body_template.clone(),
+             reasoning_mode: service.reasoning_mode.unwrap_or_default(),
+             completions_path: service.completions_path.clone().unwrap_or_else(|| "/api/chat".to_string()),
+             models_path: service.models_path.clone().unwrap_or_else(|| "/api/tags".to_string()),
+             agent: build_agent(service)?,
          })
     }
-    fn complete(&self, prompt: &str) -> Result<(String, Option<String>)> {
-        let mut messages = Vec::new();
-        messages.push(json!({"role": "system", "content": self.system_prompt}));
-        messages.push(json!({"role": "user", "content": prompt}));
-        
-        let body = json!({
-            "model": self.model,
-            "messages": messages,
-            "stream": false
-        });
-
-        let base_url = self.url.trim_end_matches('/');
-        let endpoint = format!("{}/api/chat", base_url);
+    fn complete(&self, messages: &[Message]) -> Result<Completion> {
+        let response = self.send(self.request_body(messages, false)?)?;
+        self.parse_response(response)
+    }
 
-        let mut req = ureq::post(&endpoint);
-        
-        if let Some(key) = &self.api_key {
-            req = req.set("Authorization", &format!("Bearer {}", key));
+    fn complete_streaming(&self, messages: &[Message], sink: &mut dyn Write) -> Result<Completion> {
+        let response = self.send(self.request_body(messages, true)?)?;
+        if response.content_type() == "application/json" {
+            let completion = self.parse_response(response)?;
+            sink.write_all(completion.text.as_bytes())?;
+            sink.flush()?;
+            return Ok(completion);
         }
 
-        let res = req.send_json(body);
+        // Newline-delimited JSON, or server-sent events from some gateways
+        let mut writer = StreamWriter::new(sink, self.reasoning_mode, ReasoningMode::Field);
+        for_each_stream_chunk(response, |chunk| {
+            // The final chunk carries the token counts
+            writer.set_usage(Usage::parse(chunk, "prompt_eval_count", "eval_count"));
+            writer.set_truncated(chunk["done_reason"] == "length");
+            writer.push(chunk["message"]["content"].as_str(), chunk["message"]["thinking"].as_str())
+        })?;
+        writer.finish()
+    }
 
-        match res {
-             Ok(response) => {
-                 let json: serde_json::Value = response.into_json().context("Failed to parse Ollama response")?;
-                 let response_text = json["message"]["content"]
-                    .as_str()
-                    .map(|s| s.to_string())
-                    .context("Invalid response format from Ollama")?;
-                 
-                 // Extract thinking if present
-                 // Note: Ollama might return it in a different way depending on model or custom fields?
-                 // User said: "chain of reasoning comes in the 'thinking' field of the response"
-                 // This implies it's a top-level field or inside the message object?
-                 // Usually for chat API it's inside message object? Or maybe for /api/generate it is separate?
-                 // User said "thinking" field. Let's assume top level or message level.
-                 // Let's check both for robustness.
-                 let thinking = json.get("thinking")
-                     .or_else(|| json["message"].get("thinking"))
-                     .and_then(|t| t.as_str())
-                     .map(|s| s.to_string());
-                     
-                 Ok((response_text, thinking))
-            },
-            Err(ureq::Error::Status(code, response)) => {
-                 let text = response.into_string().unwrap_or_default();
-                 match code {
-                     404 => bail!("{}", t!("api_error_not_found")),
-                     _ => bail!("Ollama API error: Status: {}, Body: {}", code, text),
-                 }
-            },
-            Err(e) => bail!("Request failed: {}", e),
-        }
+    fn build_request(&self, messages: &[Message]) -> Result<(String, serde_json::Value)> {
+        let endpoint = join_url(&self.url, &self.completions_path);
+        Ok((url_with_query(&self.agent, &endpoint, &self.query_params), self.request_body(messages, false)?))
     }
 
     fn model(&self) -> &str {
@@ -90,16 +201,69 @@ impl LLMService for OllamaDriver {
         &self.system_prompt
     }
 
+    fn supports_prefill(&self) -> bool {
+        true
+    }
+
+    fn set_max_tokens(&mut self, max_tokens: u32) {
+        self.max_tokens = Some(max_tokens);
+    }
+
+    fn set_temperature(&mut self, temperature: f64) {
+        self.temperature = Some(temperature);
+    }
+
+    fn set_stop(&mut self, stop: &[String]) {
+        self.stop = stop.to_vec();
+    }
+
+    fn append_system_prompt(&mut self, text: &str) {
+        self.system_prompt.push_str(text);
+    }
+
+    fn set_system_prompt(&mut self, text: &str) {
+        self.system_prompt = text.to_string();
+    }
+
+    fn set_response_schema(&mut self, schema: &serde_json::Value) -> bool {
+        // A body template decides the whole request, so the schema has to go in the prompt
+        if self.body_template.is_some() {
+            return false;
+        }
+        self.response_schema = Some(schema.clone());
+        true
+    }
+
+    fn model_info(&self, model: &str) -> Result<serde_json::Value> {
+        let endpoint = join_url(&self.url, "/api/show");
+
+        let mut req = with_host_header(with_query_params(self.agent.post(&endpoint), &self.query_params), &self.host_header);
+        if let Some(key) = &self.api_key {
+            req = req.set("Authorization", &format!("Bearer {}", key));
+        }
+
+        let req = with_headers(req, &self.headers);
+        match send_with_retries(&req, Some(&json!({ "model": model })), self.max_retries) {
+            Ok(response) => response.into_json().context("Failed to parse Ollama show response"),
+            Err(ureq::Error::Status(404, _)) => bail!("{}", t!("model_info_not_found", model = model, service = "Ollama")),
+            Err(ureq::Error::Status(code, response)) => {
+                 let text = response.into_string().unwrap_or_default();
+                 bail!("Ollama API error: Status: {}, Body: {}", code, text)
+            },
+            Err(e) => Err(request_failed(e)),
+        }
+    }
+
     fn list_models(&self) -> Result<Vec<String>> {
-        let base_url = self.url.trim_end_matches('/');
-        let endpoint = format!("{}/api/tags", base_url);
+        let endpoint = join_url(&self.url, &self.models_path);
 
-        let mut req = ureq::get(&endpoint);
+        let mut req = with_host_header(with_query_params(self.agent.get(&endpoint), &self.query_params), &self.host_header);
         if let Some(key) = &self.api_key {
             req = req.set("Authorization", &format!("Bearer {}", key));
         }
 
-        let res = req.call();
+        let req = with_headers(req, &self.headers);
+        let res = send_with_retries(&req, None, self.max_retries);
 
         match res {
             Ok(response) => {
@@ -116,9 +280,12 @@ impl LLMService for OllamaDriver {
             },
             Err(ureq::Error::Status(code, response)) => {
                  let text = response.into_string().unwrap_or_default();
-                 bail!("Ollama API error: Status: {}, Body: {}", code, text);
+                 match code {
+                     404 | 405 => bail!("{}", t!("list_models_not_supported", service = "Ollama")),
+                     _ => bail!("Ollama API error: Status: {}, Body: {}", code, text),
+                 }
             },
-            Err(e) => bail!("Request failed: {}", e),
+            Err(e) => Err(request_failed(e)),
         }
     }
 }
@@ -1,82 +1,252 @@
 use anyhow::{Result, bail, Context};
 use serde_json::json;
 use rust_i18n::t;
-use crate::config::Service;
-use super::LLMService;
+use crate::config::{Message, ReasoningMode, Service};
+use std::collections::HashMap;
+use std::io::Write;
+use super::{Completion, LLMService, Usage, OPENAI_DEFAULT_URL, build_agent, extract_reasoning, for_each_stream_chunk, is_event_stream, join_url, read_event_stream, render_body_template, request_failed, resolve_api_key, send_with_retries, url_with_query, with_headers, with_host_header, with_query_params, StreamWriter};
 
 pub struct OpenAIDriver {
+    /// Provider name used in messages ("OpenAI", or the compatible service built on this driver)
+    name: &'static str,
     url: String,
     api_key: String,
+    /// Header carrying the bare API key, for services that don't take "Authorization: Bearer"
+    api_key_header: Option<&'static str>,
     model: String,
     system_prompt: String,
+    query_params: HashMap<String, String>,
+    host_header: Option<String>,
+    headers: HashMap<String, String>,
+    max_tokens: Option<u32>,
+    temperature: Option<f64>,
+    stop: Vec<String>,
+    response_schema: Option<serde_json::Value>,
+    max_retries: u32,
+    body_template: Option<String>,
+    reasoning_mode: ReasoningMode,
+    completions_path: String,
+    models_path: String,
+    agent: ureq::Agent,
 }
 
-impl LLMService for OpenAIDriver {
-    fn new(service: &Service, model: &str, system_prompt: &str) -> Result<Self> {
-         let url = service.url.as_deref().unwrap_or("https://api.openai.com");
-         let api_key = service.api_key.as_deref().context(t!("api_key_required", service = "OpenAI"))?;
+impl OpenAIDriver {
+    /// Driver for an OpenAI-compatible API, named `name` in messages and found at `default_url` when the
+    /// service sets no `url`
+    pub(super) fn compatible(service: &Service, model: &str, system_prompt: &str, name: &'static str, default_url: &str) -> Result<Self> {
+         let url = service.url.as_deref().unwrap_or(default_url);
+         let api_key = service.api_key.as_deref().context(t!("api_key_required", service = name))?;
+         let api_key = resolve_api_key(api_key, name)?;
          
          if system_prompt.is_empty() {
-              bail!("{}", t!("system_prompt_required", service = "OpenAI"));
+              bail!("{}", t!("system_prompt_required", service = name));
          }
          
          Ok(Self {
+             name,
              url: url.to_string(),
-             api_key: api_key.to_string(),
+             api_key,
+             api_key_header: None,
              model: model.to_string(),
              system_prompt: system_prompt.to_string(),
+             query_params: service.query_params.clone().unwrap_or_default(),
+             host_header: service.host_header.clone(),
+             headers: service.headers.clone().unwrap_or_default(),
+             max_tokens: service.max_tokens,
+             temperature: service.temperature,
+             stop: service.stop.clone().unwrap_or_default(),
+             response_schema: None,
+             max_retries: service.max_retries.unwrap_or(0),
+             body_template: service.body_template.clone(),
+             reasoning_mode: service.reasoning_mode.unwrap_or_default(),
+             completions_path: service.completions_path.clone().unwrap_or_else(|| "/v1/chat/completions".to_string()),
+             models_path: service.models_path.clone().unwrap_or_else(|| "/v1/models".to_string()),
+             agent: build_agent(service)?,
          })
     }
-    fn complete(&self, prompt: &str) -> Result<(String, Option<String>)> {
-        let mut messages = Vec::new();
-        messages.push(json!({"role": "system", "content": self.system_prompt}));
-        messages.push(json!({"role": "user", "content": prompt}));
 
-        let body = json!({
-            "model": self.model,
-            "messages": messages
-        });
+    /// Sends the API key in `header` instead of as a bearer token
+    pub(super) fn with_api_key_header(mut self, header: &'static str) -> Self {
+        self.api_key_header = Some(header);
+        self
+    }
+
+    /// Adds the API key to a request
+    fn authorize(&self, req: ureq::Request) -> ureq::Request {
+        match self.api_key_header {
+            Some(header) => req.set(header, &self.api_key),
+            None => req.set("Authorization", &format!("Bearer {}", self.api_key)),
+        }
+    }
+
+    /// Chat request body; `stream` asks for the answer as server-sent events
+    fn request_body(&self, messages: &[Message], stream: bool) -> Result<serde_json::Value> {
+        let mut chat = vec![json!({"role": "system", "content": self.system_prompt})];
+        for message in messages {
+            chat.push(json!({"role": message.role, "content": message_content(message)}));
+        }
+
+        let body = match &self.body_template {
+            Some(template) => render_body_template(template, &self.model, &self.system_prompt, messages)?,
+            None => {
+                let mut body = json!({
+                    "model": self.model,
+                    "messages": chat
+                });
+                if let Some(max_tokens) = self.max_tokens {
+                    body["max_tokens"] = json!(max_tokens);
+                }
+                if let Some(temperature) = self.temperature {
+                    body["temperature"] = json!(temperature);
+                }
+                if !self.stop.is_empty() {
+                    body["stop"] = json!(self.stop);
+                }
+                if let Some(schema) = &self.response_schema {
+                    body["response_format"] = json!({ "type": "json_schema", "json_schema": { "name": "response", "schema": schema } });
+                }
+                if stream {
+                    body["stream"] = json!(true);
+                }
+                body
+            },
+        };
+        Ok(body)
+    }
 
-        // Ensure URL doesn't end with slash before appending
-        let base_url = self.url.trim_end_matches('/');
-        let endpoint = format!("{}/v1/chat/completions", base_url);
+    /// Entries of the model listing endpoint, with everything the API says about each model
+    pub(super) fn models_data(&self) -> Result<Vec<serde_json::Value>> {
+        let endpoint = join_url(&self.url, &self.models_path);
 
-        let res = ureq::post(&endpoint)
-            .set("Authorization", &format!("Bearer {}", self.api_key))
-            .set("Content-Type", "application/json")
-            .send_json(body);
+        let req = self.authorize(with_host_header(with_query_params(self.agent.get(&endpoint), &self.query_params), &self.host_header));
+        let req = with_headers(req, &self.headers);
+        let res = send_with_retries(&req, None, self.max_retries);
 
         match res {
             Ok(response) => {
-                 let json: serde_json::Value = response.into_json().context("Failed to parse OpenAI response")?;
-                 let content = json["choices"][0]["message"]["content"]
-                    .as_str()
-                    .map(|s| s.to_string())
-                    .context("Invalid response format from OpenAI")?;
-
-                // Extract reasoning from <think> tags
-                if let Some(start) = content.find("<think>") {
-                     if let Some(end) = content.find("</think>") {
-                          let thinking = content[start + 7..end].trim().to_string();
-                          let response_part = content[end + 8..].trim().to_string();
-                          return Ok((response_part, Some(thinking)));
-                     }
+                let mut json: serde_json::Value = response.into_json().with_context(|| format!("Failed to parse {} models response", self.name))?;
+                match json["data"].take() {
+                    serde_json::Value::Array(data) => Ok(data),
+                    _ => bail!("Invalid response format from {} (missing data array)", self.name),
                 }
-                
-                Ok((content, None))
             },
+            Err(ureq::Error::Status(code, response)) => {
+                 let text = response.into_string().unwrap_or_default();
+                 match code {
+                     404 | 405 => bail!("{}", t!("list_models_not_supported", service = self.name)),
+                     _ => bail!("{} API error: Status: {}, Body: {}", self.name, code, text),
+                 }
+            },
+            Err(e) => Err(request_failed(e)),
+        }
+    }
+
+    /// Posts a chat request, turning API errors into readable messages
+    fn send(&self, body: serde_json::Value) -> Result<ureq::Response> {
+        let endpoint = join_url(&self.url, &self.completions_path);
+
+        let req = self.authorize(with_host_header(with_query_params(self.agent.post(&endpoint), &self.query_params), &self.host_header))
+            .set("Content-Type", "application/json");
+        let req = with_headers(req, &self.headers);
+        let res = send_with_retries(&req, Some(&body), self.max_retries);
+
+        match res {
+            Ok(response) => Ok(response),
             Err(ureq::Error::Status(code, response)) => {
                  let text = response.into_string().unwrap_or_default();
                  match code {
                      401 => bail!("{}", t!("api_error_unauthorized")),
                      404 => bail!("{}", t!("api_error_not_found")),
-                     _ => bail!("OpenAI API error: Status: {}, Body: {}", code, text),
+                     _ => bail!("{} API error: Status: {}, Body: {}", self.name, code, text),
                  }
             },
-            Err(e) => bail!("Request failed: {}", e),
+            Err(e) => Err(request_failed(e)),
         }
     }
 
+    /// Reads a whole (not streamed on request) chat response
+    fn parse_response(&self, response: ureq::Response) -> Result<Completion> {
+        let (content, reasoning, usage, truncated) = if is_event_stream(&response) {
+            let chunks = read_event_stream(response)?;
+            let part = |chunk: &serde_json::Value, key: &str| {
+                let choice = &chunk["choices"][0];
+                choice["delta"][key].as_str().or(choice["message"][key].as_str()).map(str::to_string)
+            };
+            let content: String = chunks.iter().filter_map(|c| part(c, "content")).collect();
+            let reasoning: String = chunks.iter().filter_map(|c| part(c, "reasoning_content")).collect();
+            let usage = chunks.iter().rev().find_map(|c| parse_usage(&c["usage"]));
+            let truncated = chunks.iter().any(|c| c["choices"][0]["finish_reason"] == "length");
+            (content, Some(reasoning), usage, truncated)
+        } else {
+            let json: serde_json::Value = response.into_json().with_context(|| format!("Failed to parse {} response", self.name))?;
+            let message = &json["choices"][0]["message"];
+            let content = message["content"]
+               .as_str()
+               .map(|s| s.to_string())
+               .with_context(|| format!("Invalid response format from {}", self.name))?;
+            // Some OpenAI-compatible servers (e.g. DeepSeek) return the reasoning apart
+            let usage = parse_usage(&json["usage"]);
+            let truncated = json["choices"][0]["finish_reason"] == "length";
+            (content, message["reasoning_content"].as_str().map(str::to_string), usage, truncated)
+        };
+
+        // Reasoning comes in <think> tags unless the service says otherwise
+        Ok(Completion::from(extract_reasoning(self.reasoning_mode, ReasoningMode::Tags, content, reasoning)).with_usage(usage).with_truncated(truncated))
+    }
+}
+
+/// Token counts of an OpenAI usage object, where cached prompt tokens are counted within `prompt_tokens`
+fn parse_usage(usage: &serde_json::Value) -> Option<Usage> {
+    Usage::parse(usage, "prompt_tokens", "completion_tokens")
+        .map(|parsed| parsed.with_cache(usage, "/prompt_tokens_details/cached_tokens", None))
+}
+
+/// Message content: plain text, or a list of parts when images are attached
+fn message_content(message: &Message) -> serde_json::Value {
+    if message.images.is_empty() {
+        return json!(message.content);
+    }
+    let mut parts = vec![json!({"type": "text", "text": message.content})];
+    for image in &message.images {
+        parts.push(json!({"type": "image_url", "image_url": {"url": image.data_url()}}));
+    }
+    json!(parts)
+}
+
+impl LLMService for OpenAIDriver {
+    fn new(service: &Service, model: &str, system_prompt: &str) -> Result<Self> {
+        Self::compatible(service, model, system_prompt, "OpenAI", OPENAI_DEFAULT_URL)
+    }
+
+    fn complete(&self, messages: &[Message]) -> Result<Completion> {
+        let response = self.send(self.request_body(messages, false)?)?;
+        self.parse_response(response)
+    }
+
+    fn complete_streaming(&self, messages: &[Message], sink: &mut dyn Write) -> Result<Completion> {
+        let response = self.send(self.request_body(messages, true)?)?;
+        if !is_event_stream(&response) {
+            let completion = self.parse_response(response)?;
+            sink.write_all(completion.text.as_bytes())?;
+            sink.flush()?;
+            return Ok(completion);
+        }
+
+        let mut writer = StreamWriter::new(sink, self.reasoning_mode, ReasoningMode::Tags);
+        for_each_stream_chunk(response, |chunk| {
+            let delta = &chunk["choices"][0]["delta"];
+            writer.set_usage(parse_usage(&chunk["usage"]));
+            writer.set_truncated(chunk["choices"][0]["finish_reason"] == "length");
+            writer.push(delta["content"].as_str(), delta["reasoning_content"].as_str())
+        })?;
+        writer.finish()
+    }
+
+    fn build_request(&self, messages: &[Message]) -> Result<(String, serde_json::Value)> {
+        let endpoint = join_url(&self.url, &self.completions_path);
+        Ok((url_with_query(&self.agent, &endpoint, &self.query_params), self.request_body(messages, false)?))
+    }
+
     fn model(&self) -> &str {
         &self.model
     }
@@ -85,32 +255,59 @@ impl LLMService for OpenAIDriver {
         &self.system_prompt
     }
 
+    fn default_model() -> Option<&'static str> {
+        Some("gpt-4o")
+    }
+
+    fn set_max_tokens(&mut self, max_tokens: u32) {
+        self.max_tokens = Some(max_tokens);
+    }
+
+    fn set_temperature(&mut self, temperature: f64) {
+        self.temperature = Some(temperature);
+    }
+
+    fn set_stop(&mut self, stop: &[String]) {
+        self.stop = stop.to_vec();
+    }
+
+    fn append_system_prompt(&mut self, text: &str) {
+        self.system_prompt.push_str(text);
+    }
+
+    fn set_system_prompt(&mut self, text: &str) {
+        self.system_prompt = text.to_string();
+    }
+
+    fn set_response_schema(&mut self, schema: &serde_json::Value) -> bool {
+        // A body template decides the whole request, so the schema has to go in the prompt
+        if self.body_template.is_some() {
+            return false;
+        }
+        self.response_schema = Some(schema.clone());
+        true
+    }
+
     fn list_models(&self) -> Result<Vec<String>> {
-        let base_url = self.url.trim_end_matches('/');
-        let endpoint = format!("{}/v1/models", base_url);
+        Ok(self.models_data()?.iter().filter_map(|d| d["id"].as_str().map(str::to_string)).collect())
+    }
 
-        let res = ureq::get(&endpoint)
-             .set("Authorization", &format!("Bearer {}", self.api_key))
-             .call();
+    fn model_info(&self, model: &str) -> Result<serde_json::Value> {
+        let endpoint = join_url(&self.url, &format!("{}/{}", self.models_path.trim_end_matches('/'), model));
 
-        match res {
-            Ok(response) => {
-                let json: serde_json::Value = response.into_json().context("Failed to parse OpenAI models response")?;
-                let data = json["data"].as_array().context("Invalid response format from OpenAI (missing data array)")?;
-                
-                let mut ids = Vec::new();
-                for d in data {
-                    if let Some(id) = d["id"].as_str() {
-                        ids.push(id.to_string());
-                    }
-                }
-                Ok(ids)
-            },
+        let req = self.authorize(with_host_header(with_query_params(self.agent.get(&endpoint), &self.query_params), &self.host_header));
+        let req = with_headers(req, &self.headers);
+        match send_with_retries(&req, None, self.max_retries) {
+            Ok(response) => response.into_json().with_context(|| format!("Failed to parse {} model response", self.name)),
+            // Some compatible APIs (e.g. OpenRouter) have no per-model endpoint, but their list has the details
+            Err(ureq::Error::Status(404 | 405, _)) => self.models_data()?.into_iter()
+                .find(|d| d["id"].as_str() == Some(model))
+                .with_context(|| t!("model_info_not_found", model = model, service = self.name)),
             Err(ureq::Error::Status(code, response)) => {
                  let text = response.into_string().unwrap_or_default();
-                 bail!("OpenAI API error: Status: {}, Body: {}", code, text);
+                 bail!("{} API error: Status: {}, Body: {}", self.name, code, text)
             },
-            Err(e) => bail!("Request failed: {}", e),
+            Err(e) => Err(request_failed(e)),
         }
     }
 }
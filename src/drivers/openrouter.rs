@@ -0,0 +1,96 @@
+use anyhow::Result;
+use crate::config::{Message, ModelPrice, Service};
+use std::io::Write;
+use super::{Completion, LLMService, OPENROUTER_URL, openai::OpenAIDriver};
+
+/// OpenRouter, a gateway to many providers' models behind an OpenAI-compatible API. Its optional
+/// `HTTP-Referer` and `X-Title` headers identify the calling app, and come from `referer` and `title`.
+pub struct OpenRouterDriver {
+    inner: OpenAIDriver,
+}
+
+impl LLMService for OpenRouterDriver {
+    fn new(service: &Service, model: &str, system_prompt: &str) -> Result<Self> {
+        // Headers set in the service's `headers` still win
+        let mut service = service.clone();
+        let headers = service.headers.get_or_insert_default();
+        if let Some(referer) = &service.referer {
+            headers.entry("HTTP-Referer".to_string()).or_insert_with(|| referer.clone());
+        }
+        if let Some(title) = &service.title {
+            headers.entry("X-Title".to_string()).or_insert_with(|| title.clone());
+        }
+
+        Ok(Self {
+            inner: OpenAIDriver::compatible(&service, model, system_prompt, "OpenRouter", OPENROUTER_URL)?,
+        })
+    }
+
+    fn complete(&self, messages: &[Message]) -> Result<Completion> {
+        self.inner.complete(messages)
+    }
+
+    fn complete_streaming(&self, messages: &[Message], sink: &mut dyn Write) -> Result<Completion> {
+        self.inner.complete_streaming(messages, sink)
+    }
+
+    fn build_request(&self, messages: &[Message]) -> Result<(String, serde_json::Value)> {
+        self.inner.build_request(messages)
+    }
+
+    fn model(&self) -> &str {
+        self.inner.model()
+    }
+
+    fn system_prompt(&self) -> &str {
+        self.inner.system_prompt()
+    }
+
+    fn default_model() -> Option<&'static str> {
+        Some("openrouter/auto")
+    }
+
+    fn set_max_tokens(&mut self, max_tokens: u32) {
+        self.inner.set_max_tokens(max_tokens);
+    }
+
+    fn set_temperature(&mut self, temperature: f64) {
+        self.inner.set_temperature(temperature);
+    }
+
+    fn set_stop(&mut self, stop: &[String]) {
+        self.inner.set_stop(stop);
+    }
+
+    fn append_system_prompt(&mut self, text: &str) {
+        self.inner.append_system_prompt(text);
+    }
+
+    fn set_system_prompt(&mut self, text: &str) {
+        self.inner.set_system_prompt(text);
+    }
+
+    fn set_response_schema(&mut self, schema: &serde_json::Value) -> bool {
+        self.inner.set_response_schema(schema)
+    }
+
+    fn model_info(&self, model: &str) -> Result<serde_json::Value> {
+        self.inner.model_info(model)
+    }
+
+    fn list_models(&self) -> Result<Vec<String>> {
+        self.inner.list_models()
+    }
+
+    fn list_priced_models(&self) -> Result<Vec<(String, Option<ModelPrice>)>> {
+        Ok(self.inner.models_data()?.iter()
+            .filter_map(|d| Some((d["id"].as_str()?.to_string(), model_price(&d["pricing"]))))
+            .collect())
+    }
+}
+
+/// A model's `pricing` in OpenRouter's list, which gives USD per token as decimal strings
+fn model_price(pricing: &serde_json::Value) -> Option<ModelPrice> {
+    let per_million = |field: &str| pricing[field].as_str()?.parse::<f64>().ok().map(|price| price * 1_000_000.0);
+    Some(ModelPrice { input: per_million("prompt")?, output: per_million("completion")? })
+}
@@ -0,0 +1,128 @@
+//! Keyword, string, number and comment colors for the fenced code blocks of --markdown.
+//! Lines are highlighted one at a time, so a block comment or string spanning lines is only
+//! colored on its first line.
+
+/// What tells the parts of a language's code apart
+pub struct Syntax {
+    keywords: &'static [&'static str],
+    /// Markers starting a comment that runs to the end of the line
+    line_comments: &'static [&'static str],
+    /// Characters opening (and closing) a string
+    quotes: &'static [char],
+}
+
+const RUST: Syntax = Syntax {
+    keywords: &["as", "async", "await", "break", "const", "continue", "crate", "dyn", "else", "enum", "extern", "false", "fn", "for", "if", "impl", "in", "let", "loop", "match", "mod", "move", "mut", "pub", "ref", "return", "self", "Self", "static", "struct", "super", "trait", "true", "type", "unsafe", "use", "where", "while"],
+    line_comments: &["//"],
+    // ' also starts lifetimes
+    quotes: &['"'],
+};
+
+const PYTHON: Syntax = Syntax {
+    keywords: &["and", "as", "assert", "async", "await", "break", "class", "continue", "def", "del", "elif", "else", "except", "False", "finally", "for", "from", "global", "if", "import", "in", "is", "lambda", "None", "nonlocal", "not", "or", "pass", "raise", "return", "True", "try", "while", "with", "yield"],
+    line_comments: &["#"],
+    quotes: &['"', '\''],
+};
+
+const JAVASCRIPT: Syntax = Syntax {
+    keywords: &["async", "await", "break", "case", "catch", "class", "const", "continue", "default", "delete", "else", "export", "extends", "false", "finally", "for", "from", "function", "if", "import", "in", "instanceof", "interface", "let", "new", "null", "of", "return", "static", "switch", "this", "throw", "true", "try", "type", "typeof", "undefined", "var", "void", "while", "yield"],
+    line_comments: &["//"],
+    quotes: &['"', '\'', '`'],
+};
+
+const GO: Syntax = Syntax {
+    keywords: &["break", "case", "chan", "const", "continue", "default", "defer", "else", "false", "for", "func", "go", "if", "import", "interface", "map", "nil", "package", "range", "return", "select", "struct", "switch", "true", "type", "var"],
+    line_comments: &["//"],
+    quotes: &['"', '\'', '`'],
+};
+
+/// C, C++, C#, Java and Kotlin share most of their keywords
+const C_LIKE: Syntax = Syntax {
+    keywords: &["auto", "bool", "break", "case", "catch", "char", "class", "const", "continue", "default", "delete", "do", "double", "else", "enum", "extends", "false", "final", "float", "for", "fun", "if", "implements", "import", "include", "int", "interface", "long", "namespace", "new", "null", "nullptr", "override", "package", "private", "protected", "public", "return", "short", "signed", "sizeof", "static", "struct", "switch", "template", "this", "throw", "true", "try", "typedef", "union", "unsigned", "using", "val", "var", "virtual", "void", "while"],
+    line_comments: &["//"],
+    quotes: &['"', '\''],
+};
+
+const SHELL: Syntax = Syntax {
+    keywords: &["case", "do", "done", "echo", "elif", "else", "esac", "exit", "export", "fi", "for", "function", "if", "in", "local", "return", "then", "until", "while"],
+    line_comments: &["#"],
+    quotes: &['"', '\''],
+};
+
+const SQL: Syntax = Syntax {
+    keywords: &["and", "as", "by", "create", "delete", "desc", "distinct", "drop", "from", "group", "having", "insert", "into", "is", "join", "left", "limit", "not", "null", "on", "or", "order", "right", "select", "set", "table", "union", "update", "values", "where", "with"],
+    line_comments: &["--"],
+    quotes: &['\''],
+};
+
+/// JSON, YAML and TOML: no keywords besides their literals
+const DATA: Syntax = Syntax {
+    keywords: &["false", "null", "true"],
+    line_comments: &["#"],
+    quotes: &['"', '\''],
+};
+
+/// The syntax of a fenced block's language tag, if it's one we know
+pub fn syntax(language: &str) -> Option<&'static Syntax> {
+    let syntax = match language.to_lowercase().as_str() {
+        "rust" | "rs" => &RUST,
+        "python" | "py" => &PYTHON,
+        "javascript" | "js" | "jsx" | "typescript" | "ts" | "tsx" => &JAVASCRIPT,
+        "go" | "golang" => &GO,
+        "c" | "h" | "cpp" | "c++" | "cc" | "csharp" | "cs" | "java" | "kotlin" | "kt" => &C_LIKE,
+        "sh" | "bash" | "shell" | "zsh" | "console" => &SHELL,
+        "sql" => &SQL,
+        "json" | "yaml" | "yml" | "toml" => &DATA,
+        _ => return None,
+    };
+    Some(syntax)
+}
+
+/// Colors one line of code: keywords yellow, strings green, numbers magenta and comments grey
+pub fn highlight(line: &str, syntax: &Syntax) -> String {
+    // SQL keywords are written in any case
+    let case_insensitive = std::ptr::eq(syntax, &SQL);
+    let mut output = String::with_capacity(line.len() * 2);
+    let mut rest = line;
+
+    while let Some(c) = rest.chars().next() {
+        if syntax.line_comments.iter().any(|marker| rest.starts_with(marker)) {
+            output.push_str(&format!("\x1b[90m{}\x1b[39m", rest));
+            break;
+        }
+        let len = if syntax.quotes.contains(&c) {
+            // Up to the closing quote, skipping escaped ones, or the end of the line
+            let mut escaped = false;
+            let end = rest.char_indices().skip(1)
+                .find(|&(_, ch)| {
+                    let closes = ch == c && !escaped;
+                    escaped = ch == '\\' && !escaped;
+                    closes
+                })
+                .map_or(rest.len(), |(i, ch)| i + ch.len_utf8());
+            output.push_str(&format!("\x1b[32m{}\x1b[39m", &rest[..end]));
+            end
+        } else if c.is_alphanumeric() || c == '_' {
+            let end = rest.find(|ch: char| !(ch.is_alphanumeric() || ch == '_')).unwrap_or(rest.len());
+            let word = &rest[..end];
+            let is_keyword = if case_insensitive {
+                syntax.keywords.iter().any(|keyword| keyword.eq_ignore_ascii_case(word))
+            } else {
+                syntax.keywords.contains(&word)
+            };
+            if is_keyword {
+                output.push_str(&format!("\x1b[33m{}\x1b[39m", word));
+            } else if c.is_ascii_digit() {
+                output.push_str(&format!("\x1b[35m{}\x1b[39m", word));
+            } else {
+                output.push_str(word);
+            }
+            end
+        } else {
+            output.push(c);
+            c.len_utf8()
+        };
+        rest = &rest[len..];
+    }
+    output
+}
@@ -0,0 +1,128 @@
+use crate::config::{Config, Message};
+use crate::drivers::{VALID_CLASSES, default_model, looks_like_placeholder, requires_api_key};
+use rust_i18n::t;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Severity {
+    Error,
+    Warning,
+}
+
+#[derive(Debug, Clone)]
+pub struct Issue {
+    pub severity: Severity,
+    pub message: String,
+}
+
+impl Issue {
+    fn error(message: impl Into<String>) -> Self {
+        Self { severity: Severity::Error, message: message.into() }
+    }
+
+    fn warning(message: impl Into<String>) -> Self {
+        Self { severity: Severity::Warning, message: message.into() }
+    }
+}
+
+/// Checks the loaded configuration for problems without touching the network.
+pub fn lint(config: &Config) -> Vec<Issue> {
+    let mut issues = Vec::new();
+
+    if config.services.is_empty() {
+        issues.push(Issue::error(t!("no_services_defined")));
+    } else if !config.services.contains_key(&config.default_service) {
+        issues.push(Issue::error(t!("lint_default_service_missing", service = config.default_service)));
+    }
+
+    if !config.system_prompts.contains_key(&config.default_prompt) {
+        issues.push(Issue::warning(t!("lint_default_prompt_missing", prompt = config.default_prompt)));
+    }
+
+    for service in config.fallback_services.iter().filter(|service| !config.services.contains_key(*service)) {
+        issues.push(Issue::error(t!("lint_fallback_service_missing", service = service)));
+    }
+
+    let mut names: Vec<&String> = config.services.keys().collect();
+    names.sort();
+
+    for name in &names {
+        let service = &config.services[*name];
+        let class = service.class.as_str();
+
+        if !VALID_CLASSES.contains(&class) {
+            issues.push(Issue::error(t!("lint_unknown_class", service = name, class = class, valid = VALID_CLASSES.join(", "))));
+            continue;
+        }
+
+        if service.model.is_none() && service.model_weights.is_none() && default_model(class).is_none() {
+            issues.push(Issue::error(t!("lint_missing_field", service = name, field = "model", class = class)));
+        }
+
+        if let (Some(model), Some(allowed)) = (&service.model, &service.allowed_models)
+            && !allowed.contains(model) {
+                issues.push(Issue::error(t!("lint_model_not_allowed", service = name, model = model)));
+        }
+
+        if service.model_weights.is_some() && service.pick_weighted_model().is_none() {
+            issues.push(Issue::error(t!("lint_model_weights_empty", service = name)));
+        }
+
+        if requires_api_key(class) && service.api_key.is_none() {
+            issues.push(Issue::error(t!("lint_missing_field", service = name, field = "api_key", class = class)));
+        }
+
+        if class == "azure" {
+            for (field, value) in [("url", &service.url), ("api_version", &service.api_version)] {
+                if value.is_none() {
+                    issues.push(Issue::error(t!("lint_missing_field", service = name, field = field, class = class)));
+                }
+            }
+        }
+
+        // Variables are only needed by the service being used, so an unset one is just a warning here
+        match service.with_env_vars(name) {
+            Ok(expanded) => {
+                if let Some(key) = &expanded.api_key
+                    && looks_like_placeholder(key) {
+                        issues.push(Issue::warning(t!("lint_placeholder_api_key", service = name)));
+                }
+            },
+            Err(err) => issues.push(Issue::warning(err.to_string())),
+        }
+
+        if let Some(prompt) = &service.system_prompt
+            && !config.system_prompts.contains_key(prompt) {
+                issues.push(Issue::warning(t!("lint_undefined_prompt", service = name, prompt = prompt)));
+        }
+    }
+
+    // Services that would send identical requests
+    for (i, a) in names.iter().enumerate() {
+        for b in &names[i + 1..] {
+            let (sa, sb) = (&config.services[*a], &config.services[*b]);
+            if sa.class == sb.class && sa.url == sb.url && sa.model == sb.model {
+                issues.push(Issue::warning(t!("lint_duplicate_service", first = a, second = b)));
+            }
+        }
+    }
+
+    let mut prompt_names: Vec<&String> = config.system_prompts.keys().collect();
+    prompt_names.sort();
+    for (i, a) in prompt_names.iter().enumerate() {
+        for b in &prompt_names[i + 1..] {
+            if config.system_prompts[*a].trim() == config.system_prompts[*b].trim() {
+                issues.push(Issue::warning(t!("lint_duplicate_prompt", first = a, second = b)));
+            }
+        }
+    }
+
+    let mut example_names: Vec<&String> = config.examples.keys().collect();
+    example_names.sort();
+    for name in example_names {
+        for message in config.examples[name].iter().filter(|m| !m.has_valid_role()) {
+            issues.push(Issue::error(t!("invalid_example_role", name = name, role = message.role, valid = Message::ROLES.join(", "))));
+        }
+    }
+
+    issues
+}
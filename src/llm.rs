@@ -1,84 +1,600 @@
-use crate::config::Config;
-use crate::drivers::{LLMService, openai::OpenAIDriver, ollama::OllamaDriver, gemini::GeminiDriver, anthropic::AnthropicDriver};
-use anyhow::{Result, bail, Context};
+use crate::capabilities::{self, Capability};
+use crate::config::{Config, ImageInput, Message, ModelPrice, Service};
+use crate::drivers::{self, Completion, LLMService, VALID_CLASSES, openai::OpenAIDriver, ollama::OllamaDriver, gemini::GeminiDriver, anthropic::AnthropicDriver, mistral::MistralDriver, azure::AzureDriver, openrouter::OpenRouterDriver};
+use anyhow::{Result, anyhow, bail, Context};
 use rust_i18n::t;
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+use std::hash::{DefaultHasher, Hash, Hasher};
+use std::io::Write;
+use std::process::Command;
+use std::time::{SystemTime, UNIX_EPOCH};
+use crate::state;
 
-pub struct Client<'a> {
-    #[allow(dead_code)]
+/// Detects the language of a text, returning its ISO 639-1 code when the guess is reliable
+pub fn detect_language(text: &str) -> Option<String> {
+    let info = whatlang::detect(text).filter(|info| info.is_reliable())?;
+    isolang::Language::from_639_3(info.lang().code())
+        .and_then(|lang| lang.to_639_1())
+        .map(|code| code.to_string())
+}
+
+/// Seconds a system prompt fetched from a URL is reused before fetching it again
+const PROMPT_URL_CACHE_TTL: u64 = 300;
+
+/// System prompt fetched from a URL, stored in the state directory by `fetch_prompt_url`
+#[derive(Serialize, Deserialize)]
+struct CachedPrompt {
+    timestamp: u64,
+    content: String,
+}
+
+/// Settings a prompt file can carry in YAML front matter, between two `---` lines at its top.
+/// They apply when the file is the query's system prompt, under the command-line options.
+#[derive(Debug, Default, Deserialize)]
+#[serde(deny_unknown_fields)]
+struct PromptSettings {
+    service: Option<String>,
+    model: Option<String>,
+    temperature: Option<f64>,
+}
+
+/// Reads a "file:" system prompt, returning the settings in its front matter (if any) and its text
+fn read_prompt_file(path: &str) -> Result<(PromptSettings, String)> {
+    let text = std::fs::read_to_string(path).with_context(|| t!("system_prompt_file_unreadable", path = path))?;
+    let Some((front_matter, body)) = split_front_matter(&text) else {
+        return Ok((PromptSettings::default(), text.trim().to_string()));
+    };
+    let settings = serde_yaml::from_str::<Option<PromptSettings>>(front_matter)
+        .with_context(|| t!("prompt_front_matter_invalid", path = path))?
+        .unwrap_or_default();
+    Ok((settings, body.trim().to_string()))
+}
+
+/// Splits text starting with a `---` line into what comes before the next `---` line and what follows it
+fn split_front_matter(text: &str) -> Option<(&str, &str)> {
+    let mut lines = text.split_inclusive('\n');
+    if lines.next()?.trim_end() != "---" {
+        return None;
+    }
+    let start = text.find('\n')? + 1;
+    let mut end = start;
+    for line in lines {
+        if line.trim_end() == "---" {
+            return Some((&text[start..end], &text[end + line.len()..]));
+        }
+        end += line.len();
+    }
+    None
+}
+
+/// Turns a system prompt into its final text. "file:<path>" reads the file (without its front
+/// matter), "cmd:<command>" runs the command through the shell and an http(s) URL is downloaded;
+/// anything else is the prompt text itself.
+fn resolve_prompt_source(prompt: &str, service: &Service) -> Result<String> {
+    if let Some(path) = prompt.strip_prefix("file:") {
+        return read_prompt_file(path.trim()).map(|(_, text)| text);
+    }
+    if let Some(command) = prompt.strip_prefix("cmd:") {
+        return run_prompt_command(command);
+    }
+    if prompt.starts_with("http://") || prompt.starts_with("https://") {
+        return fetch_prompt_url(prompt, service);
+    }
+    Ok(prompt.to_string())
+}
+
+/// Runs a command through the shell and returns its trimmed output
+fn run_prompt_command(command: &str) -> Result<String> {
+    #[cfg(target_os = "windows")]
+    let output = Command::new("cmd").args(["/C", command]).output();
+    #[cfg(not(target_os = "windows"))]
+    let output = Command::new("sh").args(["-c", command]).output();
+
+    let output = output.with_context(|| t!("prompt_command_failed", command = command))?;
+    if !output.status.success() {
+        bail!("{}", t!("prompt_command_status", command = command, status = output.status, stderr = String::from_utf8_lossy(&output.stderr).trim()));
+    }
+    Ok(String::from_utf8_lossy(&output.stdout).trim().to_string())
+}
+
+/// Downloads a system prompt with the service's agent (so its TLS settings apply).
+/// The content is reused for `PROMPT_URL_CACHE_TTL` seconds to spare rapid successive runs.
+fn fetch_prompt_url(url: &str, service: &Service) -> Result<String> {
+    let mut hasher = DefaultHasher::new();
+    url.hash(&mut hasher);
+    let file = format!("prompt_{:016x}.json", hasher.finish());
+
+    if let Ok(Some(cached)) = state::load::<CachedPrompt>(&file)
+        && now_secs().saturating_sub(cached.timestamp) < PROMPT_URL_CACHE_TTL {
+            return Ok(cached.content);
+    }
+
+    let fail = |error: String| anyhow!("{}", t!("prompt_url_failed", url = url, error = error));
+    let response = drivers::build_agent(service)?.get(url).call().map_err(|err| match err {
+        ureq::Error::Status(code, _) => fail(format!("HTTP {}", code)),
+        ureq::Error::Transport(transport) => match std::error::Error::source(&transport) {
+            Some(source) => fail(format!("{}: {}", transport.kind(), source)),
+            None => fail(transport.kind().to_string()),
+        },
+    })?;
+    let content = response.into_string().map_err(|err| fail(err.to_string()))?.trim().to_string();
+
+    // A stale copy only costs a refetch, so failing to save is not worth a warning
+    let _ = state::save(&file, &CachedPrompt { timestamp: now_secs(), content: content.clone() });
+    Ok(content)
+}
+
+/// Model list stored in the state directory by `Client::list_models_cached`
+#[derive(Serialize, Deserialize)]
+struct CachedModels {
+    timestamp: u64,
+    models: Vec<(String, Option<ModelPrice>)>,
+}
+
+/// Subdirectory of the state directory holding the answers stored by --cache
+pub const RESPONSE_CACHE_DIR: &str = "responses";
+
+/// Sent by `complete_rest` to services that can't continue a reply themselves
+const CONTINUE_INSTRUCTION: &str = "Your answer was cut off. Continue it exactly where it stopped, without repeating anything or adding any introduction.";
+
+/// Answer stored in the state directory by `Client::complete_cached`.
+/// The timestamp is kept so entries can be expired later on.
+#[derive(Serialize, Deserialize)]
+struct CachedResponse {
+    timestamp: u64,
+    completion: Completion,
+}
+
+fn now_secs() -> u64 {
+    SystemTime::now().duration_since(UNIX_EPOCH).map(|d| d.as_secs()).unwrap_or(0)
+}
+
+/// A number of seconds in its largest whole unit: 45s, 12m, 3h, 2d
+fn format_age(secs: u64) -> String {
+    match secs {
+        0..60 => format!("{}s", secs),
+        60..3600 => format!("{}m", secs / 60),
+        3600..86400 => format!("{}h", secs / 3600),
+        _ => format!("{}d", secs / 86400),
+    }
+}
+
+/// The service, model and system prompt a query would use, and how each was chosen
+pub struct Resolution<'a> {
+    pub service_name: String,
+    pub service: &'a Service,
+    pub model: Option<&'a str>,
+    /// Prompt text before any "file:", "cmd:" or URL source is produced
+    pub system_prompt: &'a str,
+    /// One line per decision, in the order they were taken
+    pub trace: Vec<String>,
+}
+
+/// Works out the service, model and system prompt for a query: command-line values win over
+/// the ones stored with --remember, then the service's settings, then the config defaults. A system prompt that isn't a key
+/// of `system_prompts` is used as literal text.
+pub fn resolve<'a>(service_name: Option<&str>, config: &'a Config, model_override: Option<&'a String>, sys_prompt_override: Option<&'a str>, lang: Option<&str>) -> Result<Resolution<'a>> {
+    let mut trace = Vec::new();
+
+    // Determine service name: command line, then --remember, then default_service
+    let remembered = &config.remembered;
+    let service_name = match service_name {
+        Some(name) => {
+            trace.push(t!("explain_service_cli", service = name).to_string());
+            name
+        },
+        None if let Some(name) = remembered.service.as_deref() => {
+            trace.push(t!("explain_service_remembered", service = name).to_string());
+            name
+        },
+        None => {
+            trace.push(t!("explain_service_default", service = config.default_service).to_string());
+            config.default_service.as_str()
+        },
+    };
+    let service = config.services.get(service_name)
+        .context(t!("service_not_found", name = service_name))?;
+    if let Some(url) = drivers::effective_url(service) {
+        trace.push(t!("explain_endpoint", class = service.class, url = url).to_string());
+    }
+
+    let remembered = Some(remembered).filter(|remembered| remembered.applies_to(service_name));
+
+    // Resolve Model (the driver's default model is the last resort)
+    let model = if let Some(model) = model_override {
+        trace.push(t!("explain_model_cli", model = model).to_string());
+        Some(model.as_str())
+    } else if let Some(model) = remembered.and_then(|remembered| remembered.model.as_deref()) {
+        trace.push(t!("explain_model_remembered", model = model).to_string());
+        Some(model)
+    } else if let Some(model) = service.pick_weighted_model() {
+        trace.push(t!("explain_model_weighted", model = model).to_string());
+        Some(model)
+    } else if let Some(model) = service.model.as_deref() {
+        trace.push(t!("explain_model_service", model = model).to_string());
+        Some(model)
+    } else if let Some(model) = drivers::default_model(&service.class) {
+        trace.push(t!("explain_model_class_default", model = model, class = service.class).to_string());
+        Some(model)
+    } else {
+        trace.push(t!("explain_model_none").to_string());
+        None
+    };
+
+    // Resolve System Prompt: use the override, the service's system_prompt or config's default_prompt
+    let sys_ref = if let Some(sys_override) = sys_prompt_override {
+        trace.push(t!("explain_prompt_cli", name = sys_override).to_string());
+        sys_override
+    } else if let Some(sys_ref) = remembered.and_then(|remembered| remembered.prompt.as_deref()) {
+        trace.push(t!("explain_prompt_remembered", name = sys_ref).to_string());
+        sys_ref
+    } else if let Some(sys_ref) = service.system_prompt.as_deref() {
+        trace.push(t!("explain_prompt_service", name = sys_ref).to_string());
+        sys_ref
+    } else {
+        trace.push(t!("explain_prompt_default", name = config.default_prompt).to_string());
+        config.default_prompt.as_str()
+    };
+
+    // With a detected language, a "<key>.<lang>" variant of the prompt key takes precedence
+    let localized = lang.and_then(|code| {
+        let key = format!("{}.{}", sys_ref, code);
+        config.system_prompts.get(&key).map(|text| (text, key, code))
+    });
+    let system_prompt = if let Some((text, key, code)) = localized {
+        trace.push(t!("explain_prompt_localized", key = key, lang = code).to_string());
+        text.as_str()
+    } else if let Some(text) = config.system_prompts.get(sys_ref) {
+        trace.push(t!("explain_prompt_key", key = sys_ref).to_string());
+        text.as_str()
+    } else {
+        // Fallback: If not found in map, treat as raw text (backward compatibility)
+        trace.push(t!("explain_prompt_literal").to_string());
+        sys_ref
+    };
+
+    if let Some(path) = system_prompt.strip_prefix("file:") {
+        trace.push(t!("explain_prompt_file", path = path.trim()).to_string());
+    } else if let Some(command) = system_prompt.strip_prefix("cmd:") {
+        trace.push(t!("explain_prompt_command", command = command).to_string());
+    } else if system_prompt.starts_with("http://") || system_prompt.starts_with("https://") {
+        trace.push(t!("explain_prompt_url", url = system_prompt).to_string());
+    }
+
+    Ok(Resolution { service_name: service_name.to_string(), service, model, system_prompt, trace })
+}
+
+/// Replaces each `{{include:<name>}}` in a system prompt with the `system_prompts` entry `name`
+/// (its "<name>.<lang>" variant when there is one), produced like any other prompt and with its
+/// own includes expanded. `chain` holds the prompts being expanded, to catch one including itself.
+fn expand_prompt_includes(text: &str, config: &Config, service: &Service, lang: Option<&str>, chain: &mut Vec<String>) -> Result<String> {
+    let include = regex::Regex::new(r"\{\{\s*include:\s*([^{}\s]+)\s*\}\}").unwrap();
+
+    let mut expanded = String::with_capacity(text.len());
+    let mut last = 0;
+    for caps in include.captures_iter(text) {
+        let (token, name) = (caps.get(0).unwrap(), &caps[1]);
+        if chain.iter().any(|included| included == name) {
+            bail!("{}", t!("prompt_include_cycle", chain = format!("{} -> {}", chain.join(" -> "), name)));
+        }
+        let localized = lang.and_then(|code| config.system_prompts.get(&format!("{}.{}", name, code)));
+        let source = localized.or_else(|| config.system_prompts.get(name))
+            .context(t!("prompt_include_unknown", name = name))?;
+
+        chain.push(name.to_string());
+        let included = resolve_prompt_source(source, service)
+            .and_then(|included| expand_prompt_includes(&included, config, service, lang, chain));
+        chain.pop();
+
+        expanded.push_str(&text[last..token.start()]);
+        expanded.push_str(&included?);
+        last = token.end();
+    }
+    expanded.push_str(&text[last..]);
+    Ok(expanded)
+}
+
+/// Builds the driver for the service's class. Chat drivers need a model and a system prompt;
+/// `Client::connect` passes placeholders, since listing models uses neither.
+fn new_driver(service_config: &Service, model: Option<&str>, system_prompt_text: Option<&str>) -> Result<Box<dyn LLMService>> {
+    let driver: Box<dyn LLMService> = match service_config.class.as_str() {
+        "openai" => {
+             let model = model.context(t!("model_required", service = "OpenAI"))?;
+             let sys_prompt = system_prompt_text.context(t!("system_prompt_required", service = "OpenAI"))?;
+             
+             Box::new(OpenAIDriver::new(service_config, model, sys_prompt)?)
+        },
+        "ollama" => {
+             let model = model.context(t!("model_required", service = "Ollama"))?;
+             let sys_prompt = system_prompt_text.context(t!("system_prompt_required", service = "Ollama"))?;
+             
+             Box::new(OllamaDriver::new(service_config, model, sys_prompt)?)
+        },
+        "gemini" => {
+             let model = model.context(t!("model_required", service = "Gemini"))?;
+             let sys_prompt = system_prompt_text.context(t!("system_prompt_required", service = "Gemini"))?;
+             
+             Box::new(GeminiDriver::new(service_config, model, sys_prompt)?)
+        },
+        "anthropic" => {
+             let model = model.context(t!("model_required", service = "Anthropic"))?;
+             let sys_prompt = system_prompt_text.context(t!("system_prompt_required", service = "Anthropic"))?;
+             
+             Box::new(AnthropicDriver::new(service_config, model, sys_prompt)?)
+        },
+        "mistral" => {
+             let model = model.context(t!("model_required", service = "Mistral"))?;
+             let sys_prompt = system_prompt_text.context(t!("system_prompt_required", service = "Mistral"))?;
+             
+             Box::new(MistralDriver::new(service_config, model, sys_prompt)?)
+        },
+        "azure" => {
+             let model = model.context(t!("model_required", service = "Azure OpenAI"))?;
+             let sys_prompt = system_prompt_text.context(t!("system_prompt_required", service = "Azure OpenAI"))?;
+             
+             Box::new(AzureDriver::new(service_config, model, sys_prompt)?)
+        },
+        "openrouter" => {
+             let model = model.context(t!("model_required", service = "OpenRouter"))?;
+             let sys_prompt = system_prompt_text.context(t!("system_prompt_required", service = "OpenRouter"))?;
+             
+             Box::new(OpenRouterDriver::new(service_config, model, sys_prompt)?)
+        },
+        _ => bail!("{}", t!("unknown_service_class_detailed", class = service_config.class, valid = VALID_CLASSES.join(", "))),
+    };
+    Ok(driver)
+}
+
+pub struct Client {
     service_name: String,
-    driver: Box<dyn LLMService + 'a>,
+    /// The service's request timeout in seconds, for error messages
+    timeout: u64,
+    driver: Box<dyn LLMService>,
+    history: Vec<Message>,
+    /// Images sent with the prompt
+    images: Vec<ImageInput>,
+    prefill: Option<String>,
 }
 
-impl<'a> Client<'a> {
-    pub fn new(service_name: Option<&str>, config: &'a Config, model_override: Option<&'a String>, sys_prompt_override: Option<&'a str>) -> Result<Self> {
-         // Determine service name
-         let service_name = service_name
-            .unwrap_or(&config.default_service);
+impl Client {
+    pub fn new(service_name: Option<&str>, config: &Config, model_override: Option<&String>, sys_prompt_override: Option<&str>, lang: Option<&str>) -> Result<Self> {
+        let mut resolution = resolve(service_name, config, model_override, sys_prompt_override, lang)?;
 
-        // Get service config
-        // Get service config
-        let service_config = config.services.get(service_name)
-            .context(t!("service_not_found", name = service_name))?;
-
-        // Resolve Model
-        let model = model_override.map(|s| s.as_str()).or(service_config.model.as_deref());
-        
-        // Resolve System Prompt
-        let system_prompt_text = if let Some(sys_override) = sys_prompt_override {
-             if let Some(text) = config.system_prompts.get(sys_override) {
-                 Some(text.as_str())
-             } else {
-                 Some(sys_override)
-             }
-        } else {
-            // Determine reference: use service's system_prompt or config's default_prompt
-            let sys_ref = service_config.system_prompt.as_ref().unwrap_or(&config.default_prompt);
-            
-            // Check if sys_ref is a key in system_prompts
-             if let Some(text) = config.system_prompts.get(sys_ref) {
-                 Some(text.as_str())
-             } else {
-                 // Fallback: If not found in map, treat as raw text (backward compatibility)
-                 Some(sys_ref.as_str())
-             }
+        // A prompt file's front matter ranks below the command line and above everything else
+        let settings = match resolution.system_prompt.strip_prefix("file:") {
+            Some(path) => read_prompt_file(path.trim())?.0,
+            None => PromptSettings::default(),
         };
-
-        // Instantiate driver
-        let driver: Box<dyn LLMService + 'a> = match service_config.class.as_str() {
-            "openai" => {
-                 let model = model.context(t!("model_required", service = "OpenAI"))?;
-                 let sys_prompt = system_prompt_text.context(t!("system_prompt_required", service = "OpenAI"))?;
-                 
-                 Box::new(OpenAIDriver::new(service_config, model, sys_prompt)?)
-            },
-            "ollama" => {
-                 let model = model.context(t!("model_required", service = "Ollama"))?;
-                 let sys_prompt = system_prompt_text.context(t!("system_prompt_required", service = "Ollama"))?;
-                 
-                 Box::new(OllamaDriver::new(service_config, model, sys_prompt)?)
-            },
-            "gemini" => {
-                 let model = model.context(t!("model_required", service = "Gemini"))?;
-                 let sys_prompt = system_prompt_text.context(t!("system_prompt_required", service = "Gemini"))?;
-                 
-                 Box::new(GeminiDriver::new(service_config, model, sys_prompt)?)
-            },
-            "anthropic" => {
-                 let model = model.context(t!("model_required", service = "Anthropic"))?;
-                 let sys_prompt = system_prompt_text.context(t!("system_prompt_required", service = "Anthropic"))?;
-                 
-                 Box::new(AnthropicDriver::new(service_config, model, sys_prompt)?)
-            },
-            _ => bail!("{}", t!("unknown_service_class_detailed", class = service_config.class, valid = "openai, ollama, gemini, anthropic")),
+        if service_name.is_none()
+            && let Some(service) = settings.service.as_deref()
+            && service != resolution.service_name {
+                // The file stays the system prompt, whichever prompt the new service would pick
+                let prompt = resolution.system_prompt;
+                resolution = resolve(Some(service), config, model_override, Some(prompt), lang)?;
+        }
+        let service_name = resolution.service_name.as_str();
+        let service_config = &resolution.service.with_env_vars(service_name)?;
+        let model = match (model_override, settings.model.as_deref()) {
+            (None, Some(model)) => Some(model),
+            _ => resolution.model,
         };
 
+        if let (Some(model), Some(allowed)) = (model, &service_config.allowed_models)
+            && !allowed.iter().any(|m| m == model) {
+                bail!("{}", t!("model_not_allowed", model = model, service = service_name, allowed = allowed.join(", ")));
+        }
+
+        // Dynamic prompts ("cmd:<command>" or a URL) are produced now, and so are the prompts they include
+        let system_prompt_text = resolve_prompt_source(resolution.system_prompt, service_config)?;
+        let system_prompt_text = Some(expand_prompt_includes(&system_prompt_text, config, service_config, lang, &mut Vec::new())?);
+
+        let mut service = service_config.with_defaults(&config.defaults);
+        if let Some(temperature) = settings.temperature {
+            service.temperature = Some(temperature);
+        }
+        let driver = new_driver(&service, model, system_prompt_text.as_deref())?;
+
         Ok(Self {
             service_name: service_name.to_string(),
+            timeout: service_config.timeout.unwrap_or(drivers::DEFAULT_TIMEOUT),
             driver,
+            history: Vec::new(),
+            images: Vec::new(),
+            prefill: None,
         })
     }
-    pub fn complete(&self, prompt: &str) -> Result<(String, Option<String>)> {
-        self.driver.complete(prompt)
+
+    /// A client for the service that can only be used to list its models: no model has to be
+    /// configured and the system prompt isn't resolved (so "cmd:" prompts don't run).
+    /// `model()` is the service's model or the class default, or empty without either.
+    pub fn connect(service_name: &str, config: &Config) -> Result<Self> {
+        let service_config = config.services.get(service_name)
+            .context(t!("service_not_found", name = service_name))?
+            .with_env_vars(service_name)?;
+        let model = service_config.model.as_deref()
+            .or_else(|| drivers::default_model(&service_config.class))
+            .unwrap_or_default();
+
+        // Drivers refuse an empty system prompt; this one is never sent
+        let driver = new_driver(&service_config, Some(model), Some("-"))?;
+
+        Ok(Self {
+            service_name: service_name.to_string(),
+            timeout: service_config.timeout.unwrap_or(drivers::DEFAULT_TIMEOUT),
+            driver,
+            history: Vec::new(),
+            images: Vec::new(),
+            prefill: None,
+        })
+    }
+
+    /// Messages sent between the system prompt and the user prompt (e.g. few-shot examples)
+    pub fn set_history(&mut self, history: Vec<Message>) {
+        self.history = history;
+    }
+
+    /// Adds messages after the current history (e.g. earlier turns of a session)
+    pub fn extend_history(&mut self, messages: &[Message]) {
+        self.history.extend_from_slice(messages);
+    }
+
+    pub fn history(&self) -> &[Message] {
+        &self.history
+    }
+
+    /// Attaches images to the prompt. Warns when the model is known not to read images.
+    pub fn set_images(&mut self, images: Vec<ImageInput>) {
+        if !images.is_empty() && capabilities::lookup(self.model()).is_some_and(|found| !found.contains(&Capability::Vision)) {
+            eprintln!("{}", t!("images_not_supported", model = self.model()));
+        }
+        self.images = images;
+    }
+
+    /// The user message carrying the prompt and the attached images
+    fn user_message(&self, prompt: &str) -> Message {
+        Message { images: self.images.clone(), ..Message::user(prompt) }
+    }
+
+    /// Seeds the start of the assistant's reply. Ignored with a warning when the service can't continue a reply.
+    pub fn set_prefill(&mut self, prefill: &str) {
+        if self.driver.supports_prefill() {
+            self.prefill = Some(prefill.to_string());
+        } else {
+            eprintln!("{}", t!("prefill_not_supported", service = self.service_name));
+        }
+    }
+
+    /// Asks the service to cache the system prompt. Ignored with a warning when the service can't.
+    pub fn set_prompt_caching(&mut self) {
+        if !self.driver.enable_prompt_caching() {
+            eprintln!("{}", t!("prompt_caching_not_supported", service = self.service_name));
+        }
+    }
+
+    /// Replaces the resolved system prompt (e.g. with its --var placeholders filled)
+    pub fn set_system_prompt(&mut self, text: &str) {
+        self.driver.set_system_prompt(text);
+    }
+
+    /// Adds `text` on a new line after the system prompt. The text is literal, even if it names a
+    /// key of `system_prompts`.
+    pub fn append_system(&mut self, text: &str) {
+        self.driver.append_system_prompt(&format!("\n{}", text));
+    }
+
+    /// Asks for an answer of at most `words` words: an instruction in the system prompt, plus a
+    /// token cap of 1.5 tokens per word. Both are approximate, since tokens are not words.
+    pub fn set_max_words(&mut self, words: u32) {
+        self.driver.append_system_prompt(&format!("\n\nRespond in at most {} words.", words));
+        self.driver.set_max_tokens(words.saturating_mul(3).div_ceil(2));
+    }
+
+    /// Sampling temperature, replacing the one from the configuration
+    pub fn set_temperature(&mut self, temperature: f64) {
+        self.driver.set_temperature(temperature);
+    }
+
+    /// Sequences that end the answer, replacing the ones from the configuration
+    pub fn set_stop(&mut self, stop: &[String]) {
+        self.driver.set_stop(stop);
+    }
+
+    /// Caps the length of the answer, in tokens, replacing the cap from the configuration
+    pub fn set_max_tokens(&mut self, max_tokens: u32) {
+        self.driver.set_max_tokens(max_tokens);
+    }
+
+    /// Asks for an answer in JSON matching `schema`: natively when the service supports structured
+    /// output, otherwise with an instruction at the end of the system prompt.
+    pub fn set_schema(&mut self, schema: &serde_json::Value) {
+        if !self.driver.set_response_schema(schema) {
+            self.driver.append_system_prompt(&format!("\n\nRespond only with a JSON document that matches this JSON schema, with no other text:\n{}", schema));
+        }
+    }
+
+    pub fn complete(&self, prompt: &str) -> Result<Completion> {
+        let mut messages = self.history.clone();
+        messages.push(self.user_message(prompt));
+
+        let Some(prefill) = &self.prefill else {
+            return self.driver.complete(&messages).map_err(|err| self.explain_timeout(err));
+        };
+
+        // The model continues after the prefill, so it has to be put back in front of the answer
+        messages.push(Message::assistant(prefill));
+        let completion = self.driver.complete(&messages).map_err(|err| self.explain_timeout(err))?;
+        Ok(Completion { text: format!("{}{}", prefill, completion.text), ..completion })
+    }
+
+    /// Asks for the rest of `partial`, an answer to `prompt` cut off by the length cap, and returns
+    /// the whole answer. Services that can continue a reply pick up where it stopped; the others
+    /// get the partial answer back with a request to go on.
+    pub fn complete_rest(&self, prompt: &str, partial: &str) -> Result<Completion> {
+        let mut messages = self.history.clone();
+        messages.push(self.user_message(prompt));
+        // Anthropic rejects a reply to continue that ends in whitespace
+        let partial = if self.driver.supports_prefill() { partial.trim_end() } else { partial };
+        messages.push(Message::assistant(partial));
+        if !self.driver.supports_prefill() {
+            messages.push(Message::user(CONTINUE_INSTRUCTION));
+        }
+        let completion = self.driver.complete(&messages).map_err(|err| self.explain_timeout(err))?;
+        Ok(Completion { text: format!("{}{}", partial, completion.text), ..completion })
+    }
+
+    /// The endpoint and body `complete` would send for `prompt`, without sending anything
+    pub fn build_request(&self, prompt: &str) -> Result<(String, serde_json::Value)> {
+        let mut messages = self.history.clone();
+        messages.push(self.user_message(prompt));
+        if let Some(prefill) = &self.prefill {
+            messages.push(Message::assistant(prefill));
+        }
+        self.driver.build_request(&messages)
+    }
+
+    /// Like `complete`, but writes the answer to `sink` as it arrives. The reasoning isn't written.
+    pub fn complete_streaming(&self, prompt: &str, sink: &mut dyn Write) -> Result<Completion> {
+        let mut messages = self.history.clone();
+        messages.push(self.user_message(prompt));
+
+        let Some(prefill) = &self.prefill else {
+            return self.driver.complete_streaming(&messages, sink).map_err(|err| self.explain_timeout(err));
+        };
+
+        messages.push(Message::assistant(prefill));
+        sink.write_all(prefill.as_bytes())?;
+        let completion = self.driver.complete_streaming(&messages, sink).map_err(|err| self.explain_timeout(err))?;
+        Ok(Completion { text: format!("{}{}", prefill, completion.text), ..completion })
+    }
+
+    /// Runs `complete` unless this exact request was answered before, and stores new answers. The
+    /// key is the service and the body the driver would send, so the model, system prompt, messages
+    /// (with the prompt's images and the prefill), schema and sampling settings (temperature,
+    /// max_tokens, stop...) all count. Answers from the cache report no usage, since they cost nothing.
+    /// An answer stored more than `max_age` seconds ago counts as missing and is asked again.
+    pub fn complete_cached(&self, prompt: &str, max_age: Option<u64>, complete: impl FnOnce() -> Result<Completion>) -> Result<Completion> {
+        let (_, body) = self.build_request(prompt)?;
+        let request = serde_json::json!([self.service_name, body]);
+        let hash: String = Sha256::digest(request.to_string()).iter().map(|byte| format!("{:02x}", byte)).collect();
+        let file = format!("{}/{}.json", RESPONSE_CACHE_DIR, hash);
+
+        if let Ok(Some(cached)) = state::load::<CachedResponse>(&file) {
+            let age = now_secs().saturating_sub(cached.timestamp);
+            if max_age.is_none_or(|max_age| age <= max_age) {
+                if drivers::is_verbose() {
+                    eprintln!("{}", t!("cache_hit_age", age = format_age(age), timestamp = cached.timestamp));
+                }
+                return Ok(Completion { usage: None, ..cached.completion });
+            }
+        }
+
+        let completion = complete()?;
+        let entry = CachedResponse { timestamp: now_secs(), completion: completion.clone() };
+        if let Err(err) = state::save(&file, &entry) {
+            eprintln!("{}", t!("failed_save_response_cache", error = err));
+        }
+        Ok(completion)
     }
 
     pub fn service_name(&self) -> &str {
@@ -93,7 +609,48 @@ impl<'a> Client<'a> {
         self.driver.system_prompt()
     }
 
-    pub fn list_models(&self) -> Result<Vec<String>> {
-        self.driver.list_models()
+    /// The service's models, with their prices when its API publishes them
+    pub fn list_models(&self) -> Result<Vec<(String, Option<ModelPrice>)>> {
+        self.driver.list_priced_models().map_err(|err| self.explain_timeout(err))
+    }
+
+    /// What the service's API says about `model`
+    pub fn model_info(&self, model: &str) -> Result<serde_json::Value> {
+        self.driver.model_info(model).map_err(|err| self.explain_timeout(err))
+    }
+
+    /// Adds the service and its limit to a timeout error, keeping the timeout in the chain
+    fn explain_timeout(&self, err: anyhow::Error) -> anyhow::Error {
+        if err.chain().any(drivers::is_timeout) {
+            err.context(t!("error_request_timeout", service = self.service_name, seconds = self.timeout).to_string())
+        } else {
+            err
+        }
+    }
+
+    /// Like `list_models`, but reuses a list fetched less than `ttl` seconds ago.
+    /// `refresh` forces a new fetch; a `ttl` of 0 disables the cache.
+    pub fn list_models_cached(&self, ttl: u64, refresh: bool) -> Result<Vec<(String, Option<ModelPrice>)>> {
+        if ttl == 0 {
+            return self.list_models();
+        }
+
+        let safe_name: String = self.service_name.chars()
+            .map(|c| if c.is_ascii_alphanumeric() || c == '-' || c == '_' { c } else { '_' })
+            .collect();
+        let file = format!("models_{}.json", safe_name);
+
+        if !refresh
+            && let Ok(Some(cached)) = state::load::<CachedModels>(&file)
+            && now_secs().saturating_sub(cached.timestamp) < ttl {
+                return Ok(cached.models);
+        }
+
+        let models = self.list_models()?;
+        let entry = CachedModels { timestamp: now_secs(), models };
+        if let Err(err) = state::save(&file, &entry) {
+            eprintln!("{}", t!("failed_save_model_cache", error = err));
+        }
+        Ok(entry.models)
     }
 }
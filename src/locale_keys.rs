@@ -0,0 +1,207 @@
+/// Translation keys used at runtime through `t!`, besides the argument help keys
+/// listed in `ARGS_HELP`. Keep in sync when adding messages so `--dump-locale`
+/// can report untranslated entries.
+pub const MESSAGE_KEYS: &[&str] = &[
+    "api_error_not_found",
+    "api_error_unauthorized",
+    "api_key_file_unreadable",
+    "api_key_placeholder",
+    "api_key_placeholder_warning",
+    "api_key_required",
+    "auto_continue_request",
+    "available_models_for",
+    "available_services",
+    "azure_field_required",
+    "batch_failed",
+    "batch_item_failed",
+    "body_template_invalid",
+    "ca_cert_load_failed",
+    "cache_hit_age",
+    "chat_cleared",
+    "chat_started",
+    "chat_turn_failed",
+    "chat_unknown_command",
+    "check_config_ok",
+    "capabilities_of",
+    "capabilities_unknown",
+    "cli_description",
+    "clipboard_unavailable",
+    "config_include_cycle",
+    "config_include_failed",
+    "configured_prompts",
+    "configured_services",
+    "default_prompt",
+    "default_service",
+    "default_service_not_found",
+    "default_service_set",
+    "detected_language",
+    "dump_locale_missing",
+    "ensemble_all_failed",
+    "env_var_missing",
+    "error_loading_config",
+    "error_request_timeout",
+    "examples_not_found",
+    "explain_endpoint",
+    "explain_language",
+    "explain_model_class_default",
+    "explain_model_cli",
+    "explain_model_none",
+    "explain_model_remembered",
+    "explain_model_service",
+    "explain_model_weighted",
+    "explain_prompt_cli",
+    "explain_prompt_command",
+    "explain_prompt_default",
+    "explain_prompt_file",
+    "explain_prompt_key",
+    "explain_prompt_literal",
+    "explain_prompt_localized",
+    "explain_prompt_remembered",
+    "explain_prompt_service",
+    "explain_prompt_url",
+    "explain_result_prompt",
+    "explain_service_cli",
+    "explain_service_default",
+    "explain_service_remembered",
+    "failed_init_client",
+    "failed_init_client_for_listing",
+    "failed_list_models",
+    "failed_read_batch",
+    "failed_read_config_stdin",
+    "failed_read_stdin",
+    "failed_save_last_query",
+    "failed_save_model_cache",
+    "failed_save_response_cache",
+    "failed_save_session",
+    "failed_write_log",
+    "global_config_skipped",
+    "hint_api_key",
+    "hint_connection",
+    "hint_not_found",
+    "hint_rate_limited",
+    "hint_timeout",
+    "image_unreadable",
+    "image_unsupported_format",
+    "images_not_supported",
+    "invalid_accept_if",
+    "invalid_class_display",
+    "invalid_duration",
+    "invalid_example_role",
+    "invalid_var",
+    "invalid_list_target",
+    "invalid_refusal_pattern",
+    "invalid_session_name",
+    "last_query_unreadable",
+    "lint_default_prompt_missing",
+    "lint_default_service_missing",
+    "lint_duplicate_prompt",
+    "lint_duplicate_service",
+    "lint_errors",
+    "lint_fallback_service_missing",
+    "lint_missing_field",
+    "lint_model_not_allowed",
+    "lint_model_weights_empty",
+    "lint_ok",
+    "lint_placeholder_api_key",
+    "lint_summary",
+    "lint_undefined_prompt",
+    "lint_unknown_class",
+    "lint_warnings",
+    "list_models_not_supported",
+    "migrate_backup_failed",
+    "migrate_class_normalized",
+    "migrate_done",
+    "migrate_field_renamed",
+    "migrate_moved_to_defaults",
+    "migrate_no_config",
+    "migrate_unknown_field",
+    "migrate_unknown_service_field",
+    "migrate_up_to_date",
+    "migrate_warning",
+    "migrate_write_failed",
+    "model_info_for",
+    "model_info_not_found",
+    "model_not_allowed",
+    "model_price",
+    "model_required",
+    "no_description",
+    "no_json_blocks_found",
+    "no_last_query",
+    "no_prompt_given",
+    "no_services_defined",
+    "output_unwritable",
+    "pick_cancelled",
+    "pick_invalid",
+    "pick_model",
+    "pick_prompt",
+    "pick_requires_tty",
+    "pick_service",
+    "prefill_not_supported",
+    "profile_not_found",
+    "prompt_caching_not_supported",
+    "prompt_command_failed",
+    "prompt_command_status",
+    "prompt_file_unreadable",
+    "prompt_front_matter_invalid",
+    "prompt_include_cycle",
+    "prompt_include_unknown",
+    "prompt_not_found",
+    "prompt_unused",
+    "prompt_url_failed",
+    "prompt_used_by",
+    "refusal_retry",
+    "remember_nothing",
+    "remembered_forgotten",
+    "remembered_header",
+    "remembered_none",
+    "remembered_saved",
+    "request_retry",
+    "request_retry_too_long",
+    "response_cache_cleared",
+    "response_not_accepted",
+    "response_truncated",
+    "sample_failed",
+    "samples_failed",
+    "schema_invalid",
+    "schema_unreadable",
+    "service_answer_failed",
+    "service_class_missing",
+    "service_extends_cycle",
+    "service_extends_unknown",
+    "service_not_found",
+    "session_cleared",
+    "set_default_no_config",
+    "set_default_unsupported",
+    "soft_timeout_fallback",
+    "stdin_used_twice",
+    "stream_no_fallback_services",
+    "stream_not_cached",
+    "summary_global_config",
+    "summary_local_config",
+    "summary_locale",
+    "summary_none",
+    "summary_prompts",
+    "summary_services",
+    "system_prompt_file_unreadable",
+    "system_prompt_required",
+    "template_file_unreadable",
+    "template_not_found",
+    "template_unfilled",
+    "template_unknown_placeholder",
+    "template_unmatched_brace",
+    "tls_verification_disabled",
+    "trying_fallback_service",
+    "unknown_service_class_detailed",
+    "usage_info",
+    "usage_prompt_cache",
+    "usage_tokens",
+    "usage_tokens_cost",
+    "usage_unavailable",
+    "using_fallback_response",
+    "verbose_streamed_body",
+    "vote_no_ballots",
+    "vote_no_value",
+    "vote_path_not_found",
+    "vote_tie",
+    "vote_winner",
+];
@@ -1,20 +1,34 @@
+mod capabilities;
 mod config;
 mod llm;
 mod drivers;
+mod highlight;
+mod lint;
+mod locale_keys;
+mod migrate;
+mod state;
 
 use clap::{Parser, CommandFactory, FromArgMatches};
-use config::Config;
+use config::{Config, Remembered};
+use drivers::{Completion, Usage};
 use anyhow::{Result, Context};
+use std::path::Path;
 use std::process;
-use std::io::Read;
+use std::sync::Mutex;
+use std::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
+use std::sync::mpsc;
+use std::thread;
+use std::time::Duration;
+use std::io::{IsTerminal, Read, Write};
 use regex::Regex;
+use serde::{Deserialize, Serialize};
 #[macro_use] extern crate rust_i18n;
 
 i18n!("locales");
 
 fn set_system_locale() {
     let locale = sys_locale::get_locale().unwrap_or_else(|| "en".to_string());
-    let lang_code = locale.split(|c| c == '-' || c == '_').next().unwrap_or("en");
+    let lang_code = locale.split(['-', '_']).next().unwrap_or("en");
     #[cfg(debug_assertions)]
     eprintln!("System locale: {}\nLang code: {}", locale, lang_code);
 
@@ -26,7 +40,80 @@ fn set_system_locale() {
     }
 }
 
-#[derive(Parser, Debug)]
+/// Set by --json-errors-to-stderr: failures still print a JSON document on stdout
+static JSON_ERRORS: AtomicBool = AtomicBool::new(false);
+
+/// Reports a fatal error on stderr and exits. With --json-errors-to-stderr, stdout also gets
+/// an `{"error": ...}` object so it always holds exactly one JSON document.
+fn exit_with_error(err: &anyhow::Error) -> ! {
+    report_error(err);
+    if JSON_ERRORS.load(Ordering::Relaxed) {
+        println!("{}", serde_json::json!({ "error": format!("{:#}", err) }));
+    }
+    process::exit(1);
+}
+
+/// Prints an error on stderr after an "error:" label, red at a terminal unless NO_COLOR is set.
+/// Each `.context()` layer goes on a line of its own, indented one step further than the one it
+/// explains, and common failures get a hint on how to fix them.
+fn report_error(err: &anyhow::Error) {
+    let color = std::io::stderr().is_terminal() && std::env::var_os("NO_COLOR").is_none_or(|value| value.is_empty());
+    let label = |text: &str, style: &str| if color { format!("\x1b[{}m{}\x1b[0m", style, text) } else { text.to_string() };
+
+    let mut chain = err.chain();
+    if let Some(message) = chain.next() {
+        eprintln!("{} {}", label("error:", "1;31"), message);
+    }
+    for (depth, cause) in chain.enumerate() {
+        eprintln!("{}{}", "  ".repeat(depth + 1), cause);
+    }
+    if let Some(hint) = error_hint(err) {
+        eprintln!("{} {}", label("hint:", "1;36"), hint);
+    }
+}
+
+/// What to check for errors with a well-known cause, recognized from the messages the drivers give
+fn error_hint(err: &anyhow::Error) -> Option<String> {
+    let messages: Vec<String> = err.chain().map(ToString::to_string).collect();
+    let says = |text: &str| messages.iter().any(|message| message.contains(text));
+
+    let key = if says(&t!("api_error_unauthorized")) || says("Status: 401") || says("Status: 403") {
+        "hint_api_key"
+    } else if says(&t!("api_error_not_found")) || says("Status: 404") {
+        "hint_not_found"
+    } else if says("Status: 429") {
+        "hint_rate_limited"
+    } else if err.chain().any(drivers::is_timeout) {
+        "hint_timeout"
+    } else if says("Connection refused") || says("Dns Failed") {
+        "hint_connection"
+    } else {
+        return None;
+    };
+    Some(t!(key).to_string())
+}
+
+/// Exit code of a process killed by SIGPIPE (128 + 13), what shells expect from `cmd | head`
+const BROKEN_PIPE_EXIT_CODE: i32 = 141;
+
+/// `println!` panics when the reader of stdout goes away (e.g. `askme ... | head`).
+/// Turn that panic into a silent exit, as if the process had been killed by SIGPIPE.
+fn exit_quietly_on_broken_pipe() {
+    let default_hook = std::panic::take_hook();
+    std::panic::set_hook(Box::new(move |info| {
+        let message = info.payload().downcast_ref::<String>().map(String::as_str)
+            .or_else(|| info.payload().downcast_ref::<&str>().copied())
+            .unwrap_or("");
+        if message.contains("failed printing to stdout") && message.contains("Broken pipe") {
+            process::exit(BROKEN_PIPE_EXIT_CODE);
+        }
+        default_hook(info);
+    }));
+}
+
+// Saved for --last: options added since a query was saved take their defaults when it's loaded
+#[derive(Parser, Debug, Default, Serialize, Deserialize)]
+#[serde(default)]
 #[command(
     version, 
     about, 
@@ -51,13 +138,21 @@ struct Args {
     #[arg(short = 'p', long = "prompt")]
     prompt_arg: Option<String>,
 
+    /// Read the system prompt from a file. Takes precedence over --prompt
+    #[arg(long, value_name = "PATH")]
+    system_file: Option<String>,
+
+    /// Text added on a new line after the resolved system prompt (used as is, never as a key)
+    #[arg(long, value_name = "TEXT")]
+    append_system: Option<String>,
+
     /// Show full content of a specific system prompt
     #[arg(long)]
     sprompt: Option<String>,
 
-    /// List configured services or system prompts
-    #[arg(short = 'l', long, num_args(0..=1), default_missing_value = "services")]
-    list: Option<String>,
+    /// List configured services or system prompts, optionally only those containing a term
+    #[arg(short = 'l', long, num_args(0..=2), value_names = ["TARGET", "FILTER"], default_missing_value = "services")]
+    list: Option<Vec<String>>,
 
     /// Print help
     #[arg(short, long, action = clap::ArgAction::Help)]
@@ -79,17 +174,454 @@ struct Args {
     #[arg(short = 'c', long)]
     config: Option<String>,
 
+    /// Use the settings of this profile from the configuration's `profiles`
+    #[arg(long, value_name = "NAME")]
+    profile: Option<String>,
+
+    /// Choose the service and model from a menu when -s/-m are not given
+    #[arg(long)]
+    pick: bool,
+
+    /// Ignore the cached model list and fetch it again
+    #[arg(long)]
+    refresh_models: bool,
+
+    /// Do not load the global configuration file
+    #[arg(long)]
+    no_global_config: bool,
+
     /// List available models for a service
     #[arg(long)]
     lmodels: Option<String>,
 
+    /// Show what a service's API says about one of its models (context length, capabilities...)
+    #[arg(long, num_args = 2, value_names = ["SERVICE", "MODEL"])]
+    model_info: Option<Vec<String>>,
+
     /// Extract JSON blocks from response
     #[arg(short = 'E', long)]
     extractjs: bool,
+
+    /// With --extractjs, flatten the extracted JSON into dotted paths
+    #[arg(long, requires = "extractjs")]
+    flatten: bool,
+
+    /// With --list prompts, show which services use each prompt
+    #[arg(long, requires = "list")]
+    used_by: bool,
+
+    /// Report configuration problems and exit
+    #[arg(long)]
+    lint: bool,
+
+    /// Validate the configuration silently: problems go to stderr and the exit code is non-zero
+    #[arg(long, conflicts_with = "lint")]
+    check_config: bool,
+
+    /// Show a summary of the loaded configuration
+    #[arg(long)]
+    summary: bool,
+
+    /// Few-shot example set to insert before the prompt
+    #[arg(long)]
+    examples: Option<String>,
+
+    /// Encode the response before printing (base64 or hex)
+    #[arg(long, value_enum, conflicts_with = "extractjs")]
+    encode: Option<Encoding>,
+
+    /// Seconds to wait for a connection or for the answer, overriding every service's timeout
+    #[arg(long, value_name = "SECONDS", value_parser = clap::value_parser!(u64).range(1..))]
+    timeout: Option<u64>,
+
+    /// Times to retry a request rejected with 429 or a 5xx status, overriding every service's max_retries
+    #[arg(long, value_name = "N")]
+    retries: Option<u32>,
+
+    /// Seconds to wait for the model before falling back to --fast-model
+    #[arg(long, value_name = "SECONDS", requires = "fast_model")]
+    soft_timeout: Option<u64>,
+
+    /// Model to query when --soft-timeout expires
+    #[arg(long, value_name = "MODEL", requires = "soft_timeout")]
+    fast_model: Option<String>,
+
+    /// Print every translation key and its value for a locale (for translators)
+    #[arg(long, hide = true, value_name = "CODE")]
+    dump_locale: Option<String>,
+
+    /// Text the assistant's reply must start with
+    #[arg(long, alias = "answer-prefix", value_name = "TEXT")]
+    prefill: Option<String>,
+
+    /// Use the clipboard text as the prompt, or append it to the given prompt
+    #[arg(long, conflicts_with = "batch")]
+    clipboard: bool,
+
+    /// Ask the same question N times and print every answer (a JSON array with --json)
+    #[arg(long, value_name = "N", value_parser = clap::value_parser!(u32).range(1..), conflicts_with_all = ["stream", "batch", "ensemble", "vote", "soft_timeout", "output", "output_template", "extractjs", "encode", "chat"])]
+    count: Option<u32>,
+
+    /// With --count, send up to N of the requests at the same time
+    #[arg(long, value_name = "N", requires = "count", value_parser = clap::value_parser!(u32).range(1..))]
+    parallel: Option<u32>,
+
+    /// Ask for a JSON answer matching the JSON schema in this file
+    #[arg(long, value_name = "PATH")]
+    schema: Option<String>,
+
+    /// Print the endpoint and request body that would be sent, without sending anything
+    #[arg(long, conflicts_with_all = ["batch", "ensemble", "vote", "chat", "count"])]
+    dry_run: bool,
+
+    /// Read the prompt from a file ("-" for stdin). A prompt given too is added after it
+    #[arg(long, value_name = "PATH", conflicts_with = "batch")]
+    file: Option<String>,
+
+    /// Ask for an answer of at most N words, capping the response tokens to match (approximate)
+    #[arg(long, value_name = "N", value_parser = clap::value_parser!(u32).range(1..))]
+    max_words: Option<u32>,
+
+    /// Cap the answer at N tokens, overriding the service and the config defaults
+    #[arg(long, value_name = "N", value_parser = clap::value_parser!(u32).range(1..))]
+    max_tokens: Option<u32>,
+
+    /// Sampling temperature, overriding the service and the config defaults
+    #[arg(long, value_name = "T")]
+    temperature: Option<f64>,
+
+    /// Stop the answer at this sequence; \n, \t and \\ are understood (repeatable)
+    #[arg(long, value_name = "SEQ", value_parser = parse_stop)]
+    stop: Vec<String>,
+
+    /// Ask the service to cache the system prompt between requests (Anthropic only)
+    #[arg(long)]
+    cache_prompt: bool,
+
+    /// Pick a "<prompt>.<lang>" system prompt matching the language of the input
+    #[arg(long)]
+    auto_lang: bool,
+
+    /// Re-run the previous query
+    #[arg(long)]
+    last: bool,
+
+    /// Keep the given -s, -m and -p as defaults for later runs, until --forget
+    #[arg(long, conflicts_with_all = ["input", "batch", "forget", "show_remembered"])]
+    remember: bool,
+
+    /// Drop the defaults stored with --remember
+    #[arg(long)]
+    forget: bool,
+
+    /// Show the defaults stored with --remember
+    #[arg(long, conflicts_with = "forget")]
+    show_remembered: bool,
+
+    /// Image to send along with the prompt (PNG, JPEG, GIF or WebP). Can be repeated
+    #[arg(long, value_name = "PATH", conflicts_with = "batch")]
+    image: Vec<String>,
+
+    /// Continue the named conversation: its earlier turns are sent along, and this one is added to it
+    #[arg(long, value_name = "NAME", conflicts_with_all = ["batch", "vote"])]
+    session: Option<String>,
+
+    /// Continue the last conversation started with --continue, or start it
+    #[arg(long = "continue", conflicts_with_all = ["session", "batch", "vote"])]
+    continue_last: bool,
+
+    /// Delete the conversation stored by --session
+    #[arg(long, value_name = "NAME")]
+    clear_session: Option<String>,
+
+    /// Keep the reasoning of the answers in the conversation of --session, --continue and --chat
+    #[arg(long)]
+    keep_thinking: bool,
+
+    /// Without a prompt, chat interactively: /exit quits, /clear forgets the conversation and /model <name> switches model
+    #[arg(long, conflicts_with_all = ["batch", "vote", "ensemble", "json", "output"])]
+    chat: bool,
+
+    /// In --chat, repeat the system prompt with every message instead of sending it once
+    #[arg(long, requires = "chat")]
+    resend_system: bool,
+
+    /// Reuse the stored answer when the same query was asked before, and store new answers
+    #[arg(long)]
+    cache: bool,
+
+    /// Ask the service even if `cache: true` is set in the configuration
+    #[arg(long, conflicts_with = "cache")]
+    no_cache: bool,
+
+    /// Treat answers stored by --cache longer ago than DURATION (e.g. 90s, 30m, 12h, 7d) as missing
+    #[arg(long, value_name = "DURATION", value_parser = parse_duration)]
+    max_cache_age: Option<u64>,
+
+    /// Delete all the answers stored by --cache
+    #[arg(long)]
+    clear_cache: bool,
+
+    /// Format the output with {service}, {model}, {prompt}, {think} and {response} placeholders
+    #[arg(long, value_name = "TEMPLATE", conflicts_with_all = ["json", "extractjs"])]
+    output_template: Option<String>,
+
+    /// Write the answer (or the --json document) to a file instead of stdout
+    #[arg(long, value_name = "PATH", conflicts_with_all = ["stream", "batch", "vote"])]
+    output: Option<String>,
+
+    /// Ask again up to N times when the answer looks like a refusal
+    #[arg(long, value_name = "N", default_value_t = 0)]
+    retry_on_refusal: u32,
+
+    /// When the answer is cut off by the length cap, ask for the rest (up to 3 times)
+    #[arg(long)]
+    auto_continue: bool,
+
+    /// Treat a response that doesn't match REGEX as a failure
+    #[arg(long, value_name = "REGEX")]
+    accept_if: Option<String>,
+
+    /// Print TEXT and exit successfully if the query fails
+    #[arg(long, value_name = "TEXT")]
+    fallback_response: Option<String>,
+
+    /// Treat setup warnings (such as placeholder API keys) as errors
+    #[arg(long)]
+    strict: bool,
+
+    /// Fail instead of warning when the global configuration file can't be parsed
+    #[arg(long)]
+    strict_config: bool,
+
+    /// Print the HTTP requests and responses on stderr, with the API keys masked
+    #[arg(short = 'v', long)]
+    verbose: bool,
+
+    /// Merge the given config files in order and print the resulting YAML
+    #[arg(long, num_args = 1.., value_name = "FILE")]
+    merge_config: Option<Vec<String>>,
+
+    /// Show the features (vision, tools, reasoning) a model likely supports, guessed from its name
+    #[arg(long, value_name = "MODEL")]
+    capabilities: Option<String>,
+
+    /// Upgrade the local configuration file in place, keeping a .bak copy
+    #[arg(long)]
+    migrate_config: bool,
+
+    /// Append every query and its answer to this file, one JSON object per line
+    #[arg(long, value_name = "PATH")]
+    log_file: Option<String>,
+
+    /// Make this service the default_service of the local configuration file
+    #[arg(long, value_name = "SERVICE")]
+    set_default: Option<String>,
+
+    /// With --json, send every diagnostic to stderr and keep stdout a single JSON document, even on errors
+    #[arg(long, requires = "json", conflicts_with_all = ["batch", "summary", "sprompt", "merge_config", "migrate_config", "dump_locale"])]
+    json_errors_to_stderr: bool,
+
+    /// Free-form label attached to the query metadata
+    #[arg(long, value_name = "LABEL")]
+    tag: Option<String>,
+
+    /// Send each line of FILE as a separate prompt ("-" reads stdin). With --json, prints one JSON object per line
+    #[arg(long, value_name = "FILE", conflicts_with_all = ["input", "last", "soft_timeout", "output_template", "extractjs"])]
+    batch: Option<String>,
+
+    /// Word-wrap the response to COLS columns (0 = terminal width). Code blocks are not wrapped
+    #[arg(long, value_name = "COLS", conflicts_with = "json")]
+    wrap: Option<usize>,
+
+    /// Ask these services concurrently and have --synthesize combine their answers
+    #[arg(long, value_name = "SERVICES", value_delimiter = ',', requires = "synthesize", conflicts_with_all = ["batch", "soft_timeout", "pick", "service"])]
+    ensemble: Option<Vec<String>>,
+
+    /// Service that writes the final answer from the --ensemble answers
+    #[arg(long, value_name = "SERVICE", requires = "ensemble")]
+    synthesize: Option<String>,
+
+    /// Build the prompt from this template of the config's `templates` section
+    #[arg(long, value_name = "NAME", conflicts_with = "batch")]
+    template: Option<String>,
+
+    /// Value for a {{KEY}} placeholder of the prompt, the system prompt or --template (repeatable)
+    #[arg(long, value_name = "KEY=VALUE", value_parser = parse_var, conflicts_with = "batch")]
+    var: Vec<(String, String)>,
+
+    /// Fail instead of warning when a {{KEY}} placeholder has no --var value
+    #[arg(long)]
+    strict_vars: bool,
+
+    /// Ask these services concurrently and report the majority answer
+    #[arg(long, value_name = "SERVICES", value_delimiter = ',', conflicts_with_all = ["batch", "soft_timeout", "pick", "service", "ensemble", "output_template"])]
+    vote: Option<Vec<String>>,
+
+    /// With --vote, compare the value at this dotted path of each answer's JSON instead of the whole answer
+    #[arg(long, value_name = "PATH", requires = "vote")]
+    json_path: Option<String>,
+
+    /// Show how the service, model and system prompt would be chosen, without sending the query
+    #[arg(long, conflicts_with_all = ["batch", "pick", "ensemble", "vote"])]
+    explain_resolution: bool,
+
+    /// Print the answer as it arrives (OpenAI and Ollama; other classes print it when complete)
+    #[arg(long, conflicts_with_all = ["json", "extractjs", "output_template", "wrap", "encode", "accept_if", "cache", "max_cache_age", "retry_on_refusal", "auto_continue", "soft_timeout", "batch", "ensemble", "vote"])]
+    stream: bool,
+
+    /// Show reasoning chain in a dimmed, indented panel (TTY only)
+    #[arg(long)]
+    pretty_think: bool,
+
+    /// Render markdown in the answer with terminal styles (TTY only)
+    #[arg(long)]
+    markdown: bool,
+
+    /// Print the tokens the query used (and its estimated cost, with `pricing`) on stderr
+    #[arg(long)]
+    usage: bool,
+}
+
+/// State file storing the arguments of the last query, for --last
+const LAST_QUERY_FILE: &str = "last_query.json";
+
+/// State file storing the overrides set with --remember
+const REMEMBERED_FILE: &str = "remembered.json";
+
+/// File in the sessions directory holding the conversation of a --session
+fn session_file(name: &str) -> Result<String> {
+    if name.is_empty() || !name.chars().all(|c| c.is_ascii_alphanumeric() || c == '-' || c == '_') {
+        anyhow::bail!("{}", t!("invalid_session_name", name = name));
+    }
+    Ok(format!("session_{}.json", name))
+}
+
+/// File in the sessions directory holding the conversation of --continue
+const LAST_SESSION_FILE: &str = "last_session.json";
+
+/// File of the conversation continued by --session or --continue, if any
+fn conversation_file(args: &Args) -> Result<Option<String>> {
+    if args.continue_last {
+        return Ok(Some(LAST_SESSION_FILE.to_string()));
+    }
+    args.session.as_deref().map(session_file).transpose()
+}
+
+/// Argument ids and the locale keys holding their help text
+const ARGS_HELP: &[(&str, &str)] = &[
+    ("input", "help_prompt"),
+    ("service", "help_service"),
+    ("model", "help_model"),
+    ("prompt_arg", "help_system_prompt"),
+    ("system_file", "help_system_file"),
+    ("append_system", "help_append_system"),
+    ("sprompt", "help_sprompt"),
+    ("list", "help_list"),
+    ("help", "help_help"),
+    ("version", "help_version"),
+    ("nothink", "help_nothink"),
+    ("json", "help_json"),
+    ("config", "help_config"),
+    ("profile", "help_profile"),
+    ("pick", "help_pick"),
+    ("refresh_models", "help_refresh_models"),
+    ("no_global_config", "help_no_global_config"),
+    ("lmodels", "help_lmodels"),
+    ("model_info", "help_model_info"),
+    ("extractjs", "help_extractjs"),
+    ("flatten", "help_flatten"),
+    ("used_by", "help_used_by"),
+    ("lint", "help_lint"),
+    ("check_config", "help_check_config"),
+    ("summary", "help_summary"),
+    ("examples", "help_examples"),
+    ("encode", "help_encode"),
+    ("timeout", "help_timeout"),
+    ("retries", "help_retries"),
+    ("soft_timeout", "help_soft_timeout"),
+    ("fast_model", "help_fast_model"),
+    ("explain_resolution", "help_explain_resolution"),
+    ("ensemble", "help_ensemble"),
+    ("synthesize", "help_synthesize"),
+    ("template", "help_template"),
+    ("var", "help_var"),
+    ("strict_vars", "help_strict_vars"),
+    ("vote", "help_vote"),
+    ("json_path", "help_json_path"),
+    ("prefill", "help_prefill"),
+    ("clipboard", "help_clipboard"),
+    ("file", "help_file"),
+    ("schema", "help_schema"),
+    ("dry_run", "help_dry_run"),
+    ("count", "help_count"),
+    ("parallel", "help_parallel"),
+    ("cache_prompt", "help_cache_prompt"),
+    ("max_words", "help_max_words"),
+    ("max_tokens", "help_max_tokens"),
+    ("temperature", "help_temperature"),
+    ("stop", "help_stop"),
+    ("auto_lang", "help_auto_lang"),
+    ("last", "help_last"),
+    ("remember", "help_remember"),
+    ("forget", "help_forget"),
+    ("show_remembered", "help_show_remembered"),
+    ("image", "help_image"),
+    ("session", "help_session"),
+    ("continue_last", "help_continue"),
+    ("clear_session", "help_clear_session"),
+    ("keep_thinking", "help_keep_thinking"),
+    ("chat", "help_chat"),
+    ("resend_system", "help_resend_system"),
+    ("cache", "help_cache"),
+    ("no_cache", "help_no_cache"),
+    ("max_cache_age", "help_max_cache_age"),
+    ("clear_cache", "help_clear_cache"),
+    ("output_template", "help_output_template"),
+    ("output", "help_output"),
+    ("retry_on_refusal", "help_retry_on_refusal"),
+    ("auto_continue", "help_auto_continue"),
+    ("accept_if", "help_accept_if"),
+    ("fallback_response", "help_fallback_response"),
+    ("strict", "help_strict"),
+    ("strict_config", "help_strict_config"),
+    ("verbose", "help_verbose"),
+    ("merge_config", "help_merge_config"),
+    ("capabilities", "help_capabilities"),
+    ("migrate_config", "help_migrate_config"),
+    ("set_default", "help_set_default"),
+    ("log_file", "help_log_file"),
+    ("json_errors_to_stderr", "help_json_errors_to_stderr"),
+    ("tag", "help_tag"),
+    ("batch", "help_batch"),
+    ("wrap", "help_wrap"),
+    ("stream", "help_stream"),
+    ("pretty_think", "help_pretty_think"),
+    ("markdown", "help_markdown"),
+    ("usage", "help_usage"),
+];
+
+#[derive(clap::ValueEnum, Clone, Copy, Debug, Serialize, Deserialize)]
+enum Encoding {
+    Base64,
+    Hex,
 }
 
-fn main() -> Result<()> {
+impl Encoding {
+    fn apply(self, text: &str) -> String {
+        match self {
+            Encoding::Base64 => {
+                use base64::Engine;
+                base64::engine::general_purpose::STANDARD.encode(text)
+            },
+            Encoding::Hex => text.bytes().map(|b| format!("{:02x}", b)).collect(),
+        }
+    }
+}
+
+fn main() {
     set_system_locale();
+    exit_quietly_on_broken_pipe();
     
     // Build command with translated help messages
     let mut command = Args::command();
@@ -97,67 +629,327 @@ fn main() -> Result<()> {
     
     // Override argument help messages
     // Note: Mutating args by ID which matches field names usually
-    let args_help = [
-        ("input", "help_prompt"),
-        ("service", "help_service"),
-        ("model", "help_model"),
-        ("prompt_arg", "help_system_prompt"),
-        ("sprompt", "help_sprompt"),
-        ("list", "help_list"),
-        ("help", "help_help"),
-        ("version", "help_version"),
-        ("nothink", "help_nothink"),
-        ("json", "help_json"),
-        ("config", "help_config"),
-        ("lmodels", "help_lmodels"),
-        ("extractjs", "help_extractjs"),
-    ];
-
-    for (arg_id, help_key) in args_help {
+    for &(arg_id, help_key) in ARGS_HELP {
          let help_msg = t!(help_key).to_string();
          command = command.mut_arg(arg_id, |a| a.help(help_msg));
     }
 
     let matches = command.get_matches();
-    let args = Args::from_arg_matches(&matches).unwrap_or_else(|e| e.exit());
+    let mut args = Args::from_arg_matches(&matches).unwrap_or_else(|e| e.exit());
 
-    let config = Config::load(args.config.clone()).unwrap_or_else(|err| {
-        eprintln!("{}", t!("error_loading_config", error = err));
-        process::exit(1);
-    });
+    if args.last {
+        args = state::load::<Args>(LAST_QUERY_FILE)
+            .context(t!("last_query_unreadable"))
+            .and_then(|last| last.context(t!("no_last_query")))
+            .unwrap_or_else(|err| exit_with_error(&err));
+        args.last = false;
+    }
+
+    JSON_ERRORS.store(args.json && args.json_errors_to_stderr, Ordering::Relaxed);
+    if let Err(err) = run(args) {
+        exit_with_error(&err);
+    }
+}
+
+fn run(mut args: Args) -> Result<()> {
+    if let Some(code) = &args.dump_locale {
+        let keys = ARGS_HELP.iter().map(|&(_, key)| key).chain(locale_keys::MESSAGE_KEYS.iter().copied());
+        let mut missing = 0;
+        for key in keys {
+            // An untranslated key comes back as the key itself
+            let value = t!(key, locale = code);
+            if rust_i18n::available_locales!().contains(&code.as_str()) && value != key {
+                println!("{}={}", key, value);
+            } else {
+                println!("{}=<MISSING>", key);
+                missing += 1;
+            }
+        }
+        eprintln!("{}", t!("dump_locale_missing", count = missing, code = code));
+        return Ok(());
+    }
+
+    drivers::set_strict(args.strict);
+    drivers::set_verbose(args.verbose);
+
+    // A broken schema is reported before any request is sent
+    if let Some(path) = &args.schema {
+        load_schema(path)?;
+    }
+
+    if let Some(pattern) = &args.accept_if {
+        Regex::new(pattern).with_context(|| t!("invalid_accept_if", pattern = pattern))?;
+    }
+
+    // stdin can only feed one of them
+    let stdin_users = [args.config.as_deref(), args.input.as_deref(), args.batch.as_deref(), args.file.as_deref()];
+    if stdin_users.iter().filter(|source| **source == Some("-")).count() > 1 {
+        anyhow::bail!("{}", t!("stdin_used_twice"));
+    }
+
+    if let Some(model) = &args.capabilities {
+        let found = capabilities::lookup(model);
+        if args.json {
+            let mut output = serde_json::json!({ "model": model, "known": found.is_some() });
+            for capability in capabilities::Capability::ALL {
+                output[capability.name()] = serde_json::json!(found.map(|c| c.contains(capability)));
+            }
+            println!("{}", output);
+        } else {
+            match found {
+                Some(found) => {
+                    let names: Vec<&str> = found.iter().map(|c| c.name()).collect();
+                    println!("{}", t!("capabilities_of", model = model, capabilities = names.join(", ")));
+                },
+                None => println!("{}", t!("capabilities_unknown", model = model)),
+            }
+        }
+        return Ok(());
+    }
+
+    if args.forget {
+        state::remove(REMEMBERED_FILE)?;
+        println!("{}", t!("remembered_forgotten"));
+        return Ok(());
+    }
+
+    if let Some(name) = &args.clear_session {
+        state::remove_session(&session_file(name)?)?;
+        println!("{}", t!("session_cleared", name = name));
+        return Ok(());
+    }
+
+    if args.clear_cache {
+        state::remove_dir(llm::RESPONSE_CACHE_DIR)?;
+        println!("{}", t!("response_cache_cleared"));
+        return Ok(());
+    }
+
+    if args.show_remembered {
+        let remembered = state::load::<Remembered>(REMEMBERED_FILE)?.unwrap_or_default();
+        if args.json {
+            println!("{}", serde_json::to_string_pretty(&remembered)?);
+        } else if remembered.is_empty() {
+            println!("{}", t!("remembered_none"));
+        } else {
+            println!("{}", t!("remembered_header"));
+            for (field, value) in [("service", &remembered.service), ("model", &remembered.model), ("prompt", &remembered.prompt)] {
+                if let Some(value) = value {
+                    println!("- {}: {}", field, value);
+                }
+            }
+        }
+        return Ok(());
+    }
+
+    if let Some(files) = &args.merge_config {
+        print!("{}", Config::merged_yaml(files, args.no_global_config)?);
+        return Ok(());
+    }
+
+    if args.migrate_config {
+        let path = Config::local_config_path(args.config.clone())
+            .filter(|path| path != Path::new("-"))
+            .with_context(|| t!("migrate_no_config"))?;
+        let contents = std::fs::read_to_string(&path).with_context(|| format!("Failed to load config at {:?}", path))?;
+        let migration = migrate::migrate(&contents).with_context(|| format!("Failed to load config at {:?}", path))?;
+
+        for warning in &migration.warnings {
+            eprintln!("{}", t!("migrate_warning", warning = warning));
+        }
+        for change in &migration.changes {
+            println!("- {}", change);
+        }
+        match &migration.yaml {
+            Some(yaml) => {
+                let backup = migrate::write_with_backup(&path, yaml)?;
+                println!("{}", t!("migrate_done", path = path.display(), backup = backup.display()));
+            },
+            None => println!("{}", t!("migrate_up_to_date", path = path.display())),
+        }
+        return Ok(());
+    }
+
+    let mut config = Config::load(args.config.clone(), args.no_global_config, args.strict_config, args.profile.as_deref())
+        .context(t!("error_loading_config"))?;
+
+    // The file is read when the prompt is resolved, like a "file:" entry of system_prompts
+    if let Some(path) = args.system_file.take() {
+        args.prompt_arg = Some(format!("file:{}", path));
+    }
+
+    if let Some(service) = &args.set_default {
+        if !config.services.contains_key(service) {
+            anyhow::bail!("{}", t!("service_not_found", name = service));
+        }
+        // The file that was loaded, or the user configuration to be
+        let path = config.local_path.clone()
+            .or_else(|| dirs::config_dir().map(|dir| dir.join("askme.yml")))
+            .filter(|path| path != Path::new("-"))
+            .with_context(|| t!("set_default_no_config"))?;
+        let contents = if path.exists() {
+            std::fs::read_to_string(&path).with_context(|| format!("Failed to load config at {:?}", path))?
+        } else {
+            String::new()
+        };
+        let updated = migrate::set_default_service(&contents, service).with_context(|| format!("Failed to load config at {:?}", path))?;
+        std::fs::write(&path, updated).with_context(|| t!("migrate_write_failed", path = path.display()))?;
+        println!("{}", t!("default_service_set", service = service, path = path.display()));
+        return Ok(());
+    }
+
+    if args.remember {
+        let remembered = Remembered { service: args.service.clone(), model: args.model.clone(), prompt: args.prompt_arg.clone() };
+        if remembered.is_empty() {
+            anyhow::bail!("{}", t!("remember_nothing"));
+        }
+        if let Some(service) = &remembered.service
+            && !config.services.contains_key(service) {
+                anyhow::bail!("{}", t!("service_not_found", name = service));
+        }
+        state::save(REMEMBERED_FILE, &remembered)?;
+        println!("{}", t!("remembered_saved"));
+        return Ok(());
+    }
+    config.remembered = state::load(REMEMBERED_FILE).ok().flatten().unwrap_or_default();
+    if let Some(secs) = args.timeout {
+        for service in config.services.values_mut() {
+            service.timeout = Some(secs);
+        }
+    }
+    if let Some(retries) = args.retries {
+        for service in config.services.values_mut() {
+            service.max_retries = Some(retries);
+        }
+    }
+    if let Some(path) = &args.log_file {
+        config.log_file = Some(path.clone());
+    }
+    config.tag = args.tag.clone();
+
+    // Quiet counterpart of --lint for scripts: only problems are printed, and the exit code tells the result.
+    // A valid configuration is confirmed only at a terminal, so pipelines stay silent.
+    if args.check_config {
+        let problems: Vec<lint::Issue> = lint::lint(&config).into_iter()
+            .filter(|issue| issue.severity == lint::Severity::Error || args.strict)
+            .collect();
+        for issue in &problems {
+            eprintln!("{}", issue.message);
+        }
+        if !problems.is_empty() {
+            process::exit(1);
+        }
+        if std::io::stdout().is_terminal() {
+            println!("{}", t!("check_config_ok"));
+        }
+        return Ok(());
+    }
+
+    if args.lint {
+        let issues = lint::lint(&config);
+        let errors: Vec<&str> = issues.iter().filter(|i| i.severity == lint::Severity::Error).map(|i| i.message.as_str()).collect();
+        let warnings: Vec<&str> = issues.iter().filter(|i| i.severity == lint::Severity::Warning).map(|i| i.message.as_str()).collect();
+
+        if args.json {
+            let output = serde_json::json!({
+                "errors": errors,
+                "warnings": warnings
+            });
+            println!("{}", output);
+        } else if issues.is_empty() {
+            println!("{}", t!("lint_ok"));
+        } else {
+            if !errors.is_empty() {
+                println!("{}", t!("lint_errors"));
+                for msg in &errors {
+                    println!("- {}", msg);
+                }
+            }
+            if !warnings.is_empty() {
+                println!("{}", t!("lint_warnings"));
+                for msg in &warnings {
+                    println!("- {}", msg);
+                }
+            }
+            println!("{}", t!("lint_summary", errors = errors.len(), warnings = warnings.len()));
+        }
+
+        if !errors.is_empty() {
+            process::exit(1);
+        }
+        return Ok(());
+    }
+
+    if args.summary {
+        let mut by_class: std::collections::BTreeMap<&str, usize> = std::collections::BTreeMap::new();
+        for service in config.services.values() {
+            *by_class.entry(service.class.as_str()).or_default() += 1;
+        }
+        let locale = rust_i18n::locale().to_string();
+
+        if args.json {
+            let output = serde_json::json!({
+                "services": config.services.len(),
+                "services_by_class": by_class,
+                "prompts": config.system_prompts.len(),
+                "global_config": config.global_path,
+                "local_config": config.local_path,
+                "locale": locale
+            });
+            println!("{}", output);
+        } else {
+            let none = t!("summary_none").to_string();
+            println!("{}", t!("summary_services", count = config.services.len()));
+            for (class, count) in &by_class {
+                println!("  - {}: {}", class, count);
+            }
+            println!("{}", t!("summary_prompts", count = config.system_prompts.len()));
+            println!("{}", t!("summary_global_config", path = config.global_path.as_ref().map(|p| p.display().to_string()).unwrap_or_else(|| none.clone())));
+            println!("{}", t!("summary_local_config", path = config.local_path.as_ref().map(|p| p.display().to_string()).unwrap_or(none)));
+            println!("{}", t!("summary_locale", lang = locale));
+        }
+        return Ok(());
+    }
 
     if config.services.is_empty() {
-        eprintln!("{}", t!("no_services_defined"));
-        process::exit(1);
+        anyhow::bail!("{}", t!("no_services_defined"));
     }
 
-    if let Some(list_target) = args.list {
+    if let Some(list_args) = args.list {
+        let list_target = &list_args[0];
+        // Case-insensitive, and matched as plain text
+        let filter = list_args.get(1).map(|term| Regex::new(&format!("(?i){}", regex::escape(term))).unwrap());
+        let matches = |texts: &[&str]| filter.as_ref().is_none_or(|filter| texts.iter().any(|text| filter.is_match(text)));
         match list_target.to_lowercase().as_str() {
             "services" | "s" => {
+                let services: Vec<(&String, &config::Service)> = config.services.iter()
+                    .filter(|(name, service)| matches(&[name.as_str(), service.description.as_deref().unwrap_or_default(), service.model.as_deref().unwrap_or_default()]))
+                    .collect();
                 if args.json {
                      let mut service_list = Vec::new();
-                     for (name, service) in &config.services {
+                     for (name, service) in services {
                          service_list.push(serde_json::json!({
                              "name": name,
                              "type": service.class,
                              "model": service.model.as_deref().unwrap_or("None"),
-                             "descr": service.description.as_deref().unwrap_or("")
+                             "descr": service.description.as_deref().unwrap_or(""),
+                             "url": drivers::effective_url(service),
+                             "requires_api_key": drivers::requires_api_key(&service.class)
                          }));
                      }
                      let output = serde_json::json!({
                          "default": config.default_service,
                          "services": service_list
                      });
-                     println!("{}", output.to_string());
+                     println!("{}", output);
                 } else {
                     println!("{}", t!("configured_services"));
-                    for (name, service) in &config.services {
+                    for (name, service) in services {
                         let prefix = if name == &config.default_service { "*" } else { "-" };
                         let desc = service.description.clone().unwrap_or_else(|| t!("no_description").to_string());
                         let model = service.model.as_deref().unwrap_or("None");
                         
-                        let valid_classes = ["openai", "ollama", "gemini", "anthropic"];
-                        let class_display = if valid_classes.contains(&service.class.as_str()) {
+                        let class_display = if drivers::VALID_CLASSES.contains(&service.class.as_str()) {
                             service.class.clone()
                         } else {
                             t!("invalid_class_display").to_string()
@@ -168,37 +960,49 @@ fn main() -> Result<()> {
                 }
             },
             "prompts" | "p" => {
+                let prompt_users = config.prompt_users();
+                let prompts: Vec<(&String, &String)> = config.system_prompts.iter()
+                    .filter(|(name, prompt)| matches(&[name.as_str(), prompt.as_str()]))
+                    .collect();
                 if args.json {
                      let mut prompt_list = Vec::new();
-                     for (name, prompt) in &config.system_prompts {
-                         prompt_list.push(serde_json::json!({
+                     for (name, prompt) in prompts {
+                         let mut entry = serde_json::json!({
                              "name": name,
                              "prompt": prompt
-                         }));
+                         });
+                         if args.used_by {
+                             entry["used_by"] = serde_json::json!(prompt_users.get(name).cloned().unwrap_or_default());
+                         }
+                         prompt_list.push(entry);
                      }
                      let output = serde_json::json!({
                          "default": config.default_prompt,
                          "prompts": prompt_list
                      });
-                     println!("{}", output.to_string());
+                     println!("{}", output);
                 } else {
                     println!("{}", t!("configured_prompts"));
-                    for (name, prompt) in &config.system_prompts {
+                    let highlight = std::io::stdout().is_terminal();
+                    for (name, prompt) in prompts {
                         let prefix = if name == &config.default_prompt { "*" } else { "-" };
-                        // Get first line and truncate
-                        let first_line = prompt.lines().next().unwrap_or("");
-                        let display_prompt = if first_line.len() > 50 {
-                            format!("{}...", &first_line[..47])
-                        } else {
-                            first_line.to_string()
-                        };
-                        println!("{} {} : \"{}\"", prefix, name, display_prompt);
+                        // The first line, or with a filter the first line that matches
+                        let line = filter.as_ref()
+                            .and_then(|filter| prompt.lines().find(|line| filter.is_match(line)))
+                            .or_else(|| prompt.lines().next())
+                            .unwrap_or("");
+                        println!("{} {} : \"{}\"", prefix, name, prompt_excerpt(line, filter.as_ref(), highlight));
+                        if args.used_by {
+                            match prompt_users.get(name) {
+                                Some(users) => println!("{}", t!("prompt_used_by", services = users.join(", "))),
+                                None => println!("{}", t!("prompt_unused")),
+                            }
+                        }
                     }
                 }
             },
             _ => {
-                eprintln!("{}", t!("invalid_list_target", target = list_target));
-                process::exit(1);
+                anyhow::bail!("{}", t!("invalid_list_target", target = list_target));
             }
         }
         return Ok(());
@@ -208,80 +1012,265 @@ fn main() -> Result<()> {
         if let Some(prompt_content) = config.system_prompts.get(&sprompt_name) {
             println!("{}", prompt_content);
         } else {
-            eprintln!("{}", t!("prompt_not_found", name = sprompt_name));
-            process::exit(1);
+            anyhow::bail!("{}", t!("prompt_not_found", name = sprompt_name));
+        }
+        return Ok(());
+    }
+
+    if let Some([service_name, model]) = args.model_info.as_deref() {
+        let client = llm::Client::connect(service_name, &config).context(t!("failed_init_client_for_listing"))?;
+        let info = client.model_info(model)?;
+
+        if args.json {
+            println!("{}", serde_json::to_string_pretty(&info)?);
+        } else {
+            println!("{}", t!("model_info_for", model = model, service = service_name));
+            print_model_info(&info, "");
         }
         return Ok(());
     }
 
     if let Some(service_name) = args.lmodels {
-        // Instantiate Client just to get the driver
-        // We don't strictly need model or system prompt for listing models, but constructor might require them.
-        // We can pass None for overrides.
-        // But constructor requires resolving a model from config if not overridden.
-        // If config has no model, constructor might fail if we don't pass one?
-        // Let's check constructor. It tries to resolve model. If service config has no model, it errors for OpenAI/Ollama.
-        // We might need to handle this.
-        // However, list_models shouldn't require a model to be selected.
-        // Our current architecture ties Driver instantiation to having a valid model configuration.
-        // For now, let's try to instantiate. If it fails due to missing model config, that's an existing constraint.
-        // Ideally we'd separate "Connecting" from "Configuring a Chat Session".
-        // But for this task, let's reuse Client::new.
-        
-        // Use a dummy model if needed? Or rely on config.
-        // If config is missing model, user must provide one via CLI, but here we don't have -m for lmodels?
-        // Actually Args has model option.
-        
-        let client = llm::Client::new(
-             Some(&service_name),
-             &config,
-             args.model.as_ref(), // Pass model if user provided it (might help initialization)
-             None // No system prompt needed
-        ).context(t!("failed_init_client_for_listing"))?;
+        // Listing needs neither a model nor a system prompt
+        let client = llm::Client::connect(&service_name, &config).context(t!("failed_init_client_for_listing"))?;
 
-        let models = client.list_models().context(t!("failed_list_models"))?;
+        let models = client.list_models_cached(config.model_cache_ttl, args.refresh_models).context(t!("failed_list_models"))?;
 
         if args.json {
-             let json_output = serde_json::to_string_pretty(&models).context("Failed to serialize models list")?;
+             let names: Vec<&String> = models.iter().map(|(model, _)| model).collect();
+             let json_output = serde_json::to_string_pretty(&names).context("Failed to serialize models list")?;
              println!("{}", json_output);
         } else {
              println!("{}", t!("available_models_for", service = service_name));
-             for model in models {
-                 println!("- {}", model);
+             for (model, price) in models {
+                 match price {
+                     Some(price) => println!("- {} {}", model, t!("model_price", input = format_price(price.input), output = format_price(price.output))),
+                     None => println!("- {}", model),
+                 }
              }
         }
         return Ok(());
     }
 
-    let mut input_text = args.input;
-    if let Some(p) = &input_text {
-        if p == "-" {
+    if let Some(file) = &args.batch {
+        return run_batch(&config, &args, file);
+    }
+
+    let mut input_text = args.input.clone();
+    if let Some(p) = &input_text
+        && p == "-" {
             let mut buffer = String::new();
             std::io::stdin().read_to_string(&mut buffer).context(t!("failed_read_stdin"))?;
             input_text = Some(buffer);
-        }
     }
 
-    if let Some(final_input) = input_text {
-        
-        // Instantiate Client
-        // Client::new handles checking if prompt_arg is a key in config or literal
-        let client = llm::Client::new(
-            args.service.as_deref(),
-            &config,
+    // The file holds the main text, and a prompt given with it follows as the question
+    if let Some(path) = &args.file {
+        let contents = if path == "-" {
+            let mut buffer = String::new();
+            std::io::stdin().read_to_string(&mut buffer).context(t!("failed_read_stdin"))?;
+            buffer
+        } else {
+            std::fs::read_to_string(path).with_context(|| t!("prompt_file_unreadable", path = path))?
+        };
+        input_text = Some(match input_text {
+            Some(prompt) => format!("{}\n\n{}", contents, prompt),
+            None => contents,
+        });
+    }
+
+    if args.clipboard {
+        let clipboard = read_clipboard()?;
+        input_text = Some(match input_text {
+            Some(prompt) => format!("{}\n\n{}", prompt, clipboard),
+            None => clipboard,
+        });
+    }
+
+    if let Some(name) = &args.template {
+        input_text = Some(render_prompt_template(&config.prompt_template(name)?, &args.var, input_text.as_deref(), args.strict || args.strict_vars)?);
+    } else if !args.var.is_empty()
+        && let Some(input) = &input_text {
+            input_text = Some(render_prompt_template(input, &args.var, None, args.strict || args.strict_vars)?);
+    }
+
+    if args.explain_resolution {
+        let lang = input_text.as_deref().filter(|_| args.auto_lang).and_then(llm::detect_language);
+        return explain_resolution(&config, &args, lang.as_deref());
+    }
+
+    if args.chat && input_text.is_none() {
+        return run_chat(&config, &args);
+    }
+
+    if let Some(final_input) = input_text {
+
+        // Catch template mistakes before spending a request
+        if let Some(template) = &args.output_template {
+            render_output_template(template, &[])?;
+        }
+
+        if args.pick {
+            pick_service_and_model(&config, &mut args)?;
+        }
+
+        // Remember the invocation (with stdin, --file, --clipboard and --template already resolved) so --last can replay it.
+        // The --var values stay, since the system prompt is filled each time.
+        args.input = Some(final_input.clone());
+        args.file = None;
+        args.clipboard = false;
+        args.template = None;
+        if !args.dry_run
+            && let Err(err) = state::save(LAST_QUERY_FILE, &args) {
+                eprintln!("{}", t!("failed_save_last_query", error = err));
+        }
+
+        let lang = if args.auto_lang { llm::detect_language(&final_input) } else { None };
+        if args.auto_lang && args.verbose {
+            eprintln!("{}", t!("detected_language", lang = lang.as_deref().unwrap_or("-")));
+        }
+
+        let images = args.image.iter().map(|path| config::ImageInput::load(Path::new(path))).collect::<Result<Vec<_>>>()?;
+
+        if let Some(services) = &args.vote {
+            return run_vote(&config, &args, services, &final_input, &images, lang.as_deref());
+        }
+
+        // Instantiate Client (with --ensemble, the one writing the final answer)
+        // Client::new handles checking if prompt_arg is a key in config or literal
+        let mut client = llm::Client::new(
+            args.synthesize.as_deref().or(args.service.as_deref()),
+            &config,
             args.model.as_ref(),
-            args.prompt_arg.as_deref()
+            args.prompt_arg.as_deref(),
+            lang.as_deref()
         ).context(t!("failed_init_client"))?;
+        configure_client(&mut client, &args, &config)?;
+
+        // Earlier turns of the conversation go after the examples
+        let session_file = conversation_file(&args)?;
+        let session = match &session_file {
+            Some(file) => state::load_session::<Vec<config::Message>>(file)?.unwrap_or_default(),
+            None => Vec::new(),
+        };
+        client.extend_history(&session);
+        client.set_images(images.clone());
+
+        if args.dry_run {
+            let (endpoint, body) = client.build_request(&final_input)?;
+            println!("{}", serde_json::to_string_pretty(&serde_json::json!({ "endpoint": endpoint, "body": body }))?);
+            return Ok(());
+        }
+
+        if let Some(count) = args.count.filter(|count| *count > 1) {
+            return run_samples(&config, &args, &client, &final_input, count);
+        }
+
+        // The answer goes straight to stdout; the reasoning is left out. Once printed, it can't be
+        // swapped for a cached one or another service's, so neither applies.
+        if args.stream {
+            if config.cache && !args.no_cache && drivers::is_verbose() {
+                eprintln!("{}", t!("stream_not_cached"));
+            }
+            match client.complete_streaming(&final_input, &mut std::io::stdout()) {
+                Ok(completion) => {
+                    println!();
+                    if completion.truncated {
+                        eprintln!("{}", t!("response_truncated"));
+                    }
+                    log_query(&config, &client, &final_input, &completion);
+                    if let Some(file) = &session_file {
+                        save_session_turn(file, session, &final_input, &history_answer(&completion, args.keep_thinking || config.keep_thinking));
+                    }
+                    if args.usage {
+                        report_usage(&config, client.model(), completion.usage);
+                    }
+                },
+                Err(err) if err.downcast_ref::<std::io::Error>().is_some_and(|e| e.kind() == std::io::ErrorKind::BrokenPipe) => {
+                    process::exit(BROKEN_PIPE_EXIT_CODE);
+                },
+                Err(err) => {
+                    if !config.fallback_services.is_empty() {
+                        eprintln!("{}", t!("stream_no_fallback_services"));
+                    }
+                    println!("{}", with_fallback_response(Err(err), &args)?.text)
+                },
+            }
+            return Ok(());
+        }
 
         // Execute query
-        let (response, thinking) = client.complete(&final_input)?;
-        
+        let mut ensemble = None;
+        let result = if let Some(services) = &args.ensemble {
+            let answers = ask_services(&config, &args, services, &final_input, &images, lang.as_deref());
+            if answers.iter().all(|answer| answer.response.is_none()) {
+                anyhow::bail!("{}", t!("ensemble_all_failed"));
+            }
+            let result = complete_with_refusal_retries(&client, &synthesis_prompt(&final_input, &answers), &config, args.retry_on_refusal, args.auto_continue);
+            ensemble = Some(answers);
+            result
+        } else if let (Some(secs), Some(fast_model)) = (args.soft_timeout, &args.fast_model) {
+            let history = client.history().to_vec();
+            match complete_with_soft_timeout(client, &config, &args, &final_input, Duration::from_secs(secs)) {
+                Some((primary, result)) => {
+                    client = primary;
+                    result
+                },
+                None => {
+                    eprintln!("{}", t!("soft_timeout_fallback", seconds = secs, model = fast_model));
+                    client = llm::Client::new(
+                        args.service.as_deref(),
+                        &config,
+                        Some(fast_model),
+                        args.prompt_arg.as_deref(),
+                        lang.as_deref()
+                    ).context(t!("failed_init_client"))?;
+                    configure_client(&mut client, &args, &config)?;
+                    client.set_history(history);
+                    client.set_images(images.clone());
+                    complete_with_refusal_retries(&client, &final_input, &config, args.retry_on_refusal, args.auto_continue)
+                }
+            }
+        } else if (args.cache || config.cache) && !args.no_cache {
+            client.complete_cached(&final_input, args.max_cache_age, || complete_with_refusal_retries(&client, &final_input, &config, args.retry_on_refusal, args.auto_continue))
+        } else {
+            complete_with_refusal_retries(&client, &final_input, &config, args.retry_on_refusal, args.auto_continue)
+        };
+        // While the answer fails or isn't accepted, the fallback services are asked in turn
+        let mut result = accept_response(result, &args);
+        for service in &config.fallback_services {
+            let Err(err) = &result else { break };
+            if service == client.service_name() {
+                continue;
+            }
+            eprintln!("{}", t!("trying_fallback_service", service = service, error = format!("{:#}", err)));
+            result = ask_fallback_service(service, &config, &args, &final_input, &session, &images, lang.as_deref())
+                .map(|(fallback, completion)| {
+                    client = fallback;
+                    completion
+                });
+        }
+        if let (Some(file), Ok(completion)) = (&session_file, &result) {
+            save_session_turn(file, session, &final_input, &history_answer(completion, args.keep_thinking || config.keep_thinking));
+        }
+        let completion = with_fallback_response(result, &args)?;
+        let (response, thinking, usage, truncated) = (completion.text, completion.thinking, completion.usage, completion.truncated);
+
+        // Binary-safe output: encode before anything gets printed
+        let (response, thinking) = match args.encode {
+            Some(encoding) => (encoding.apply(&response), thinking.map(|t| encoding.apply(&t))),
+            None => (response, thinking),
+        };
+
         let extracted_json = if args.extractjs {
-            extract_json_blocks(&response)
+            extract_json_blocks(&response).map(|data| if args.flatten { flatten_json(&data) } else { data })
         } else {
             None
         };
 
+        // Everything meant for stdout is gathered here, so --output can send it to a file instead
+        let mut out = Vec::new();
+        let to_terminal = args.output.is_none() && std::io::stdout().is_terminal();
         if args.json {
              let response_val = if args.extractjs {
                  extracted_json.unwrap_or(serde_json::Value::Null)
@@ -289,20 +1278,33 @@ fn main() -> Result<()> {
                  serde_json::Value::String(response.clone())
              };
 
-             let output = serde_json::json!({
+             let mut output = serde_json::json!({
                  "service": client.service_name(),
                  "model": client.model(),
                  "system_prompt": client.system_prompt(),
                  "prompt": final_input,
                  "response": response_val,
-                 "think": thinking
+                 "think": thinking,
+                 "usage": usage_json(&config, client.model(), usage),
+                 "truncated": truncated,
+                 "tag": args.tag
              });
-             println!("{}", output.to_string());
+             if let (Some(answers), serde_json::Value::Object(map)) = (&ensemble, &mut output) {
+                 map.insert("ensemble".to_string(), serde_json::json!(answers));
+             }
+             writeln!(out, "{}", output)?;
         } else {
             if args.extractjs {
-                if let Some(json_data) = extracted_json {
+                if let Some(serde_json::Value::Object(flat)) = extracted_json.as_ref().filter(|_| args.flatten) {
+                    for (path, value) in flat {
+                        match value {
+                            serde_json::Value::String(text) => writeln!(out, "{}: {}", path, text)?,
+                            other => writeln!(out, "{}: {}", path, other)?,
+                        }
+                    }
+                } else if let Some(json_data) = extracted_json {
                     // Print the JSON data directly (pretty printed)
-                    println!("{}", serde_json::to_string_pretty(&json_data).unwrap_or_else(|_| json_data.to_string()));
+                    writeln!(out, "{}", serde_json::to_string_pretty(&json_data).unwrap_or_else(|_| json_data.to_string()))?;
                 } else {
                     // If no JSON found, print error or nothing?
                     // "Si la respuesta contiene varios bloques JSON, devolverá un array con todos ellos."
@@ -311,16 +1313,52 @@ fn main() -> Result<()> {
                      // Let's print nothing to stdout, maybe warning to stderr
                      eprintln!("{}", t!("no_json_blocks_found"));
                 }
+            } else if let Some(template) = &args.output_template {
+                let think = if args.nothink { "" } else { thinking.as_deref().unwrap_or("") };
+                writeln!(out, "{}", render_output_template(template, &[
+                    ("service", client.service_name()),
+                    ("model", client.model()),
+                    ("prompt", &final_input),
+                    ("think", think),
+                    ("response", &response),
+                ])?)?;
             } else {
-                if !args.nothink {
-                     if let Some(thought) = thinking {
-                         println!("<think>\n{}\n</think>", thought);
-                     }
+                // Encoded answers are not markdown any more
+                let markdown = args.markdown && args.encode.is_none() && to_terminal;
+                if !args.nothink
+                    && let Some(thought) = thinking {
+                         if args.output.is_some() {
+                             // The file only gets the answer
+                             eprintln!("<think>\n{}\n</think>", thought);
+                         } else if args.pretty_think && to_terminal {
+                             print_thinking_panel(&thought, markdown);
+                         } else if markdown {
+                             writeln!(out, "<think>\n{}\n</think>", render_markdown(&thought))?;
+                         } else {
+                             writeln!(out, "<think>\n{}\n</think>", thought)?;
+                         }
+                }
+                let response = match args.wrap {
+                    Some(cols) => wrap_text(&response, wrap_width(cols)),
+                    None => response,
+                };
+                if markdown {
+                    writeln!(out, "{}", render_markdown(&response))?;
+                } else {
+                    writeln!(out, "{}", response)?;
                 }
-                println!("{}", response);
             }
         }
+        match &args.output {
+            Some(path) => write_output_file(Path::new(path), &out)?,
+            None => print!("{}", String::from_utf8_lossy(&out)),
+        }
+        if args.usage {
+            report_usage(&config, client.model(), usage);
+        }
 
+    } else if JSON_ERRORS.load(Ordering::Relaxed) {
+        anyhow::bail!("{}", t!("no_prompt_given"));
     } else {
         println!("{}", t!("cli_description"));
         println!("{}", t!("usage_info"));
@@ -347,6 +1385,1157 @@ fn main() -> Result<()> {
     Ok(())
 }
 
+/// Writes `contents` to `path`, creating its parent directories when needed
+fn write_output_file(path: &Path, contents: &[u8]) -> Result<()> {
+    if let Some(parent) = path.parent().filter(|parent| !parent.as_os_str().is_empty()) {
+        std::fs::create_dir_all(parent).with_context(|| t!("output_unwritable", path = path.display()))?;
+    }
+    std::fs::write(path, contents).with_context(|| t!("output_unwritable", path = path.display()))
+}
+
+/// Sends every non-empty line of `file` as an independent prompt, printing each result as soon as it
+/// arrives. With --json each result is a compact JSON object on its own line (NDJSON) carrying the
+/// prompt's index. A failed prompt doesn't stop the batch, but makes the process exit with an error.
+fn run_batch(config: &Config, args: &Args, file: &str) -> Result<()> {
+    let read = if file == "-" {
+        let mut contents = String::new();
+        std::io::stdin().read_to_string(&mut contents).map(|_| contents)
+    } else {
+        std::fs::read_to_string(file)
+    };
+    let contents = read.with_context(|| t!("failed_read_batch", path = file))?;
+
+    let mut client = llm::Client::new(
+        args.service.as_deref(),
+        config,
+        args.model.as_ref(),
+        args.prompt_arg.as_deref(),
+        None
+    ).context(t!("failed_init_client"))?;
+    configure_client(&mut client, args, config)?;
+
+    let mut failed = 0;
+    let mut stdout = std::io::stdout();
+    let prompts = contents.lines().map(str::trim).filter(|line| !line.is_empty());
+    for (index, prompt) in prompts.enumerate() {
+        let result = with_fallback_response(complete_with_refusal_retries(&client, prompt, config, args.retry_on_refusal, args.auto_continue), args).map(|completion| match args.encode {
+            Some(encoding) => Completion {
+                text: encoding.apply(&completion.text),
+                thinking: completion.thinking.map(|t| encoding.apply(&t)),
+                ..completion
+            },
+            None => completion,
+        });
+        if result.is_err() {
+            failed += 1;
+        }
+
+        if args.json {
+            let (response, thinking, usage, truncated, error) = match result {
+                Ok(completion) => (Some(completion.text), completion.thinking, completion.usage, completion.truncated, None),
+                Err(err) => (None, None, None, false, Some(format!("{:#}", err))),
+            };
+            let output = serde_json::json!({
+                "index": index,
+                "service": client.service_name(),
+                "model": client.model(),
+                "prompt": prompt,
+                "response": response,
+                "think": thinking,
+                "usage": usage_json(config, client.model(), usage),
+                "truncated": truncated,
+                "error": error,
+                "tag": args.tag
+            });
+            println!("{}", output);
+        } else {
+            match result {
+                Ok(completion) => {
+                    if index > 0 {
+                        println!();
+                    }
+                    if !args.nothink
+                        && let Some(thought) = completion.thinking {
+                            println!("<think>\n{}\n</think>", thought);
+                    }
+                    match args.wrap {
+                        Some(cols) => println!("{}", wrap_text(&completion.text, wrap_width(cols))),
+                        None => println!("{}", completion.text),
+                    }
+                    if args.usage {
+                        report_usage(config, client.model(), completion.usage);
+                    }
+                },
+                Err(err) => eprintln!("{}", t!("batch_item_failed", index = index, error = format!("{:#}", err))),
+            }
+        }
+        if let Err(err) = stdout.flush() {
+            if err.kind() == std::io::ErrorKind::BrokenPipe {
+                process::exit(BROKEN_PIPE_EXIT_CODE);
+            }
+            return Err(err.into());
+        }
+    }
+
+    if failed > 0 {
+        anyhow::bail!("{}", t!("batch_failed", count = failed));
+    }
+    Ok(())
+}
+
+/// Lets the user choose, on the terminal, the service and model not given with -s/-m.
+/// Menus go to stderr so stdout only carries the answer.
+fn pick_service_and_model(config: &Config, args: &mut Args) -> Result<()> {
+    if args.service.is_some() && args.model.is_some() {
+        return Ok(());
+    }
+    if !std::io::stdin().is_terminal() {
+        anyhow::bail!("{}", t!("pick_requires_tty"));
+    }
+
+    if args.service.is_none() {
+        let mut names: Vec<&String> = config.services.keys().collect();
+        names.sort();
+        let default = names.iter().position(|name| **name == config.default_service);
+        let items: Vec<String> = names.iter().map(|name| {
+            let service = &config.services[*name];
+            format!("{} ({}, {})", name, service.class, service.model.as_deref().unwrap_or("None"))
+        }).collect();
+        let choice = pick_from_menu(&t!("pick_service"), &items, default)?;
+        args.service = Some(names[choice].clone());
+    }
+
+    if args.model.is_none() {
+        let service = args.service.as_deref().unwrap_or(config.default_service.as_str());
+        let client = llm::Client::connect(service, config).context(t!("failed_init_client_for_listing"))?;
+        let models: Vec<String> = client.list_models_cached(config.model_cache_ttl, args.refresh_models).context(t!("failed_list_models"))?
+            .into_iter().map(|(model, _)| model).collect();
+        if !models.is_empty() {
+            let default = models.iter().position(|model| model == client.model());
+            let choice = pick_from_menu(&t!("pick_model"), &models, default)?;
+            args.model = Some(models[choice].clone());
+        }
+    }
+    Ok(())
+}
+
+/// Prints the plain fields of a model's metadata, one per line, with nested fields under dotted
+/// names. Long texts (e.g. Ollama's model file) and long lists (e.g. tokenizer vocabularies) are
+/// left for --json.
+fn print_model_info(info: &serde_json::Value, prefix: &str) {
+    let Some(fields) = info.as_object() else {
+        println!("{}", info);
+        return;
+    };
+    for (name, value) in fields {
+        let name = if prefix.is_empty() { name.clone() } else { format!("{}.{}", prefix, name) };
+        match value {
+            serde_json::Value::String(text) if !text.contains('\n') => println!("- {}: {}", name, text),
+            serde_json::Value::Number(_) | serde_json::Value::Bool(_) => println!("- {}: {}", name, value),
+            serde_json::Value::Array(items) if !items.is_empty() && items.len() <= 16 && items.iter().all(|item| item.is_string()) => {
+                let items: Vec<&str> = items.iter().filter_map(|item| item.as_str()).collect();
+                println!("- {}: {}", name, items.join(", "));
+            },
+            serde_json::Value::Object(_) => print_model_info(value, &name),
+            _ => {},
+        }
+    }
+}
+
+/// Shortens a line of a system prompt for --list prompts to about 50 characters. With a filter,
+/// the part around the first match is kept, and matches are shown in bold when `highlight` is set.
+fn prompt_excerpt(line: &str, filter: Option<&Regex>, highlight: bool) -> String {
+    const WIDTH: usize = 50;
+
+    let chars: Vec<char> = line.chars().collect();
+    let excerpt = if chars.len() <= WIDTH {
+        line.to_string()
+    } else {
+        // Some context before the match, without running past the end of the line
+        let start = filter.and_then(|filter| filter.find(line))
+            .map(|found| line[..found.start()].chars().count().saturating_sub(20))
+            .unwrap_or(0)
+            .min(chars.len() - (WIDTH - 3));
+        // Room for "..." on each side that gets cut
+        let kept = if start > 0 && start + WIDTH - 3 < chars.len() { WIDTH - 6 } else { WIDTH - 3 };
+        let end = start + kept;
+        format!("{}{}{}",
+            if start > 0 { "..." } else { "" },
+            chars[start..end].iter().collect::<String>(),
+            if end < chars.len() { "..." } else { "" })
+    };
+
+    match filter {
+        Some(filter) if highlight => filter.replace_all(&excerpt, "\x1b[1m$0\x1b[22m").into_owned(),
+        _ => excerpt,
+    }
+}
+
+/// Prints a numbered menu on stderr and reads the chosen number from stdin.
+/// An empty answer selects `default`, if any. Asks again on invalid input.
+fn pick_from_menu(title: &str, items: &[String], default: Option<usize>) -> Result<usize> {
+    eprintln!("{}", title);
+    for (index, item) in items.iter().enumerate() {
+        let marker = if Some(index) == default { "*" } else { " " };
+        eprintln!("{} {:>3}) {}", marker, index + 1, item);
+    }
+
+    loop {
+        eprint!("{} ", t!("pick_prompt", count = items.len()));
+        let mut answer = String::new();
+        if std::io::stdin().read_line(&mut answer)? == 0 {
+            anyhow::bail!("{}", t!("pick_cancelled"));
+        }
+        let answer = answer.trim();
+        if answer.is_empty() && let Some(index) = default {
+            return Ok(index);
+        }
+        match answer.parse::<usize>() {
+            Ok(number) if (1..=items.len()).contains(&number) => return Ok(number - 1),
+            _ => eprintln!("{}", t!("pick_invalid", count = items.len())),
+        }
+    }
+}
+
+/// Prints, step by step, how `Client::new` would pick the service, model and system prompt
+fn explain_resolution(config: &Config, args: &Args, lang: Option<&str>) -> Result<()> {
+    let resolution = llm::resolve(args.service.as_deref(), config, args.model.as_ref(), args.prompt_arg.as_deref(), lang)?;
+
+    let mut trace = resolution.trace;
+    if let Some(code) = lang {
+        trace.insert(0, t!("explain_language", lang = code).to_string());
+    }
+    if let (Some(model), Some(allowed)) = (resolution.model, &resolution.service.allowed_models)
+        && !allowed.iter().any(|m| m == model) {
+            trace.push(t!("model_not_allowed", model = model, service = resolution.service_name, allowed = allowed.join(", ")).to_string());
+    }
+
+    if args.json {
+        println!("{}", serde_json::json!({
+            "service": resolution.service_name,
+            "model": resolution.model,
+            "system_prompt": resolution.system_prompt,
+            "trace": trace,
+        }));
+    } else {
+        for (step, line) in trace.iter().enumerate() {
+            println!("{}. {}", step + 1, line);
+        }
+        println!();
+        println!("{}", t!("explain_result_prompt", text = resolution.system_prompt));
+    }
+    Ok(())
+}
+
+/// Parses a --stop argument, turning the escapes \n, \r, \t and \\ into the characters they stand for
+fn parse_stop(arg: &str) -> Result<String, String> {
+    let mut sequence = String::with_capacity(arg.len());
+    let mut chars = arg.chars();
+    while let Some(c) = chars.next() {
+        if c != '\\' {
+            sequence.push(c);
+            continue;
+        }
+        match chars.next() {
+            Some('n') => sequence.push('\n'),
+            Some('r') => sequence.push('\r'),
+            Some('t') => sequence.push('\t'),
+            Some('\\') => sequence.push('\\'),
+            Some(other) => {
+                sequence.push('\\');
+                sequence.push(other);
+            },
+            None => sequence.push('\\'),
+        }
+    }
+    Ok(sequence)
+}
+
+/// Parses a duration such as 90s, 30m, 12h or 7d into seconds. A bare number is seconds.
+fn parse_duration(arg: &str) -> Result<u64, String> {
+    let (number, unit) = match arg.trim().char_indices().find(|(_, c)| !c.is_ascii_digit()) {
+        Some((i, _)) => arg.trim().split_at(i),
+        None => (arg.trim(), "s"),
+    };
+    let scale = match unit {
+        "s" => 1,
+        "m" => 60,
+        "h" => 3600,
+        "d" => 86400,
+        _ => 0,
+    };
+    match number.parse::<u64>() {
+        Ok(value) if scale > 0 => Ok(value.saturating_mul(scale)),
+        _ => Err(t!("invalid_duration", arg = arg).to_string()),
+    }
+}
+
+/// Parses a --var argument of the form KEY=VALUE
+fn parse_var(arg: &str) -> Result<(String, String), String> {
+    match arg.split_once('=') {
+        Some((key, value)) if !key.trim().is_empty() => Ok((key.trim().to_string(), value.to_string())),
+        _ => Err(t!("invalid_var", arg = arg).to_string()),
+    }
+}
+
+/// Fills the {{KEY}} placeholders of the client's system prompt with the --var values, if any
+fn fill_system_vars(client: &mut llm::Client, vars: &[(String, String)], strict: bool) -> Result<()> {
+    if !vars.is_empty() {
+        let prompt = render_prompt_template(client.system_prompt(), vars, None, strict)?;
+        client.set_system_prompt(&prompt);
+    }
+    Ok(())
+}
+
+/// Fills the {{KEY}} placeholders of a --template (or of a prompt) with the --var values. The prompt given on the
+/// command line or stdin, if any, fills {{input}}, or is appended when the template has no such
+/// placeholder. Placeholders left unfilled are a warning, or an error with --strict.
+fn render_prompt_template(template: &str, vars: &[(String, String)], input: Option<&str>, strict: bool) -> Result<String> {
+    let placeholder = Regex::new(r"\{\{\s*([A-Za-z0-9_.-]+)\s*\}\}").unwrap();
+    let mut unfilled = Vec::new();
+    let mut input_used = false;
+    let rendered = placeholder.replace_all(template, |caps: &regex::Captures| {
+        let key = &caps[1];
+        match vars.iter().rev().find(|(name, _)| name == key).map(|(_, value)| value.as_str()) {
+            Some(value) => value.to_string(),
+            None if key == "input" && let Some(input) = input => {
+                input_used = true;
+                input.to_string()
+            },
+            None => {
+                unfilled.push(key.to_string());
+                caps[0].to_string()
+            },
+        }
+    });
+
+    if !unfilled.is_empty() {
+        let message = t!("template_unfilled", placeholders = unfilled.join(", "));
+        if strict {
+            anyhow::bail!("{}", message);
+        }
+        eprintln!("{}", message);
+    }
+    Ok(match input {
+        Some(input) if !input_used => format!("{}\n\n{}", rendered, input),
+        _ => rendered.into_owned(),
+    })
+}
+
+/// Reads the system clipboard as text
+fn read_clipboard() -> Result<String> {
+    arboard::Clipboard::new()
+        .and_then(|mut clipboard| clipboard.get_text())
+        .map_err(|err| anyhow::anyhow!("{}", t!("clipboard_unavailable", error = err)))
+}
+
+/// Prepended to the prompt when re-asking after a refusal
+const REFUSAL_RETRY_PREFACE: &str = "This is a benign request with a legitimate purpose. Please answer it directly.\n\n";
+
+/// Sends the prompt and, while the answer looks like a refusal (the config's `refusal_pattern`),
+/// asks again up to `retries` times with a clarifying preface. If every retry is refused too,
+/// the original response is returned. The usage reported covers every attempt.
+/// The answer goes to the query log, if there is one.
+fn complete_with_refusal_retries(client: &llm::Client, prompt: &str, config: &Config, retries: u32, auto_continue: bool) -> Result<Completion> {
+    let mut completion = retry_refusals(client, prompt, config, retries)?;
+    if auto_continue {
+        completion = continue_truncated(client, prompt, completion)?;
+    }
+    if completion.truncated {
+        eprintln!("{}", t!("response_truncated"));
+    }
+    log_query(config, client, prompt, &completion);
+    Ok(completion)
+}
+
+/// Most requests --auto-continue makes to finish one answer
+const MAX_CONTINUATIONS: u32 = 3;
+
+/// Asks for the rest of an answer cut off by the length cap until it's complete, joining the pieces
+fn continue_truncated(client: &llm::Client, prompt: &str, mut completion: Completion) -> Result<Completion> {
+    for attempt in 1..=MAX_CONTINUATIONS {
+        if !completion.truncated {
+            break;
+        }
+        eprintln!("{}", t!("auto_continue_request", attempt = attempt, max = MAX_CONTINUATIONS));
+        let rest = client.complete_rest(prompt, &completion.text)?;
+        completion = Completion {
+            text: rest.text,
+            thinking: completion.thinking.or(rest.thinking),
+            usage: add_usage(completion.usage, rest.usage),
+            truncated: rest.truncated,
+        };
+    }
+    Ok(completion)
+}
+
+/// Token counts of two requests together, or of whichever reported them
+fn add_usage(total: Option<Usage>, more: Option<Usage>) -> Option<Usage> {
+    match (total, more) {
+        (Some(mut total), Some(more)) => {
+            total += more;
+            Some(total)
+        },
+        (total, more) => total.or(more),
+    }
+}
+
+fn retry_refusals(client: &llm::Client, prompt: &str, config: &Config, retries: u32) -> Result<Completion> {
+    let first = client.complete(prompt)?;
+    if retries == 0 {
+        return Ok(first);
+    }
+
+    let refusal = Regex::new(&config.refusal_pattern).context(t!("invalid_refusal_pattern"))?;
+    let rephrased = format!("{}{}", REFUSAL_RETRY_PREFACE, prompt);
+    let mut usage = first.usage;
+    let mut response = first.clone();
+    for attempt in 1..=retries {
+        if !refusal.is_match(&response.text) {
+            return Ok(response.with_usage(usage));
+        }
+        eprintln!("{}", t!("refusal_retry", attempt = attempt, retries = retries));
+        response = client.complete(&rephrased)?;
+        usage = add_usage(usage, response.usage);
+    }
+    let answer = if refusal.is_match(&response.text) { first } else { response };
+    Ok(answer.with_usage(usage))
+}
+
+/// Instruction sent to the --synthesize service ahead of the question and the ensemble's answers
+const SYNTHESIS_INSTRUCTION: &str = "Several assistants answered the question below. Write the best possible answer by combining them: keep what they agree on, fix their mistakes and fill in what they missed. Reply with the answer only, without mentioning the other assistants.";
+
+/// Answer from one of the services asked by --ensemble or --vote
+#[derive(Serialize)]
+struct ServiceAnswer {
+    service: String,
+    model: Option<String>,
+    response: Option<String>,
+    think: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    usage: Option<Usage>,
+    error: Option<String>,
+}
+
+/// Sends the prompt to every service at once. Failures are reported on stderr and kept
+/// in the answer's `error`.
+fn ask_services(config: &Config, args: &Args, services: &[String], prompt: &str, images: &[config::ImageInput], lang: Option<&str>) -> Vec<ServiceAnswer> {
+    let answers: Vec<ServiceAnswer> = thread::scope(|scope| {
+        let workers: Vec<_> = services.iter().map(|service| scope.spawn(move || {
+            let mut model = None;
+            let result = llm::Client::new(Some(service), config, None, args.prompt_arg.as_deref(), lang)
+                .and_then(|mut client| {
+                    model = Some(client.model().to_string());
+                    configure_client(&mut client, args, config)?;
+                    client.set_images(images.to_vec());
+                    complete_with_refusal_retries(&client, prompt, config, args.retry_on_refusal, args.auto_continue)
+                });
+            let (response, think, usage, error) = match result {
+                Ok(completion) => (Some(completion.text), completion.thinking, completion.usage, None),
+                Err(err) => (None, None, None, Some(format!("{:#}", err))),
+            };
+            ServiceAnswer { service: service.clone(), model, response, think, usage, error }
+        })).collect();
+        workers.into_iter()
+            .map(|worker| worker.join().unwrap_or_else(|panic| std::panic::resume_unwind(panic)))
+            .collect()
+    });
+
+    for answer in &answers {
+        if let Some(error) = &answer.error {
+            eprintln!("{}", t!("service_answer_failed", service = answer.service, error = error));
+        }
+    }
+    answers
+}
+
+/// Builds the --synthesize prompt from the original question and the answers that arrived
+fn synthesis_prompt(prompt: &str, answers: &[ServiceAnswer]) -> String {
+    let mut text = format!("{}\n\nQuestion:\n{}", SYNTHESIS_INSTRUCTION, prompt);
+    for (number, response) in answers.iter().filter_map(|answer| answer.response.as_ref()).enumerate() {
+        text.push_str(&format!("\n\nAnswer {}:\n{}", number + 1, response));
+    }
+    text
+}
+
+/// Turns a response that doesn't match --accept-if into a failure
+fn accept_response(result: Result<Completion>, args: &Args) -> Result<Completion> {
+    result.and_then(|completion| match &args.accept_if {
+        Some(pattern) if !Regex::new(pattern)?.is_match(&completion.text) => {
+            anyhow::bail!("{}", t!("response_not_accepted", pattern = pattern))
+        },
+        _ => Ok(completion),
+    })
+}
+
+/// Asks one of the config's `fallback_services`, set up like the query's own client
+fn ask_fallback_service(service: &str, config: &Config, args: &Args, prompt: &str, session: &[config::Message], images: &[config::ImageInput], lang: Option<&str>) -> Result<(llm::Client, Completion)> {
+    let mut client = llm::Client::new(Some(service), config, None, args.prompt_arg.as_deref(), lang)
+        .context(t!("failed_init_client"))?;
+    configure_client(&mut client, args, config)?;
+    client.extend_history(session);
+    client.set_images(images.to_vec());
+    let completion = accept_response(complete_with_refusal_retries(&client, prompt, config, args.retry_on_refusal, args.auto_continue), args)?;
+    Ok((client, completion))
+}
+
+/// Replaces a failed completion with --fallback-response, when given, reporting the error on stderr.
+/// A response that doesn't match --accept-if counts as a failure, so it can be replaced too.
+fn with_fallback_response(result: Result<Completion>, args: &Args) -> Result<Completion> {
+    let result = accept_response(result, args);
+    match (result, &args.fallback_response) {
+        (Err(err), Some(text)) => {
+            eprintln!("{}", t!("using_fallback_response", error = format!("{:#}", err)));
+            Ok(Completion { text: text.clone(), ..Completion::default() })
+        },
+        (result, _) => result,
+    }
+}
+
+/// Adds a finished exchange to a --session or --continue conversation
+fn save_session_turn(file: &str, mut session: Vec<config::Message>, prompt: &str, response: &str) {
+    session.push(config::Message::user(prompt));
+    session.push(config::Message::assistant(response));
+    save_session(file, &session);
+}
+
+/// The answer as it goes into a conversation. Its reasoning is left out, to save tokens on later
+/// turns, unless `keep_thinking` asks for it; -n only decides what is shown.
+fn history_answer(completion: &Completion, keep_thinking: bool) -> String {
+    match &completion.thinking {
+        Some(thought) if keep_thinking => format!("<think>\n{}\n</think>\n\n{}", thought, completion.text),
+        _ => completion.text.clone(),
+    }
+}
+
+fn save_session(file: &str, session: &[config::Message]) {
+    if let Err(err) = state::save_session(file, &session) {
+        eprintln!("{}", t!("failed_save_session", file = file, error = err));
+    }
+}
+
+/// Runs --chat: reads prompts from stdin one line at a time and answers each, sending the earlier
+/// turns along. With --session or --continue the conversation starts from the stored one and is
+/// saved after every answer. Ends on /exit or end of input (Ctrl-D).
+fn run_chat(config: &Config, args: &Args) -> Result<()> {
+    let session_file = conversation_file(args)?;
+    let mut conversation = match &session_file {
+        Some(file) => state::load_session::<Vec<config::Message>>(file)?.unwrap_or_default(),
+        None => Vec::new(),
+    };
+    let mut model = args.model.clone();
+
+    // /model needs a new client: each one lives in its own round
+    loop {
+        let switch_to = {
+            let mut client = llm::Client::new(
+                args.service.as_deref(),
+                config,
+                model.as_ref(),
+                args.prompt_arg.as_deref(),
+                None
+            ).context(t!("failed_init_client"))?;
+            configure_client(&mut client, args, config)?;
+            // Every turn sends the examples and then the conversation so far
+            let examples = client.history().to_vec();
+            eprintln!("{}", t!("chat_started", service = client.service_name(), model = client.model()));
+            chat_turns(config, &mut client, &examples, &mut conversation, session_file.as_deref(), args)?
+        };
+        match switch_to {
+            Some(name) => model = Some(name),
+            None => return Ok(()),
+        }
+    }
+}
+
+/// Chats with one client until /exit or the end of input (None) or until /model asks for another model
+fn chat_turns(config: &Config, client: &mut llm::Client, examples: &[config::Message], conversation: &mut Vec<config::Message>, session_file: Option<&str>, args: &Args) -> Result<Option<String>> {
+    let stdin = std::io::stdin();
+    let markdown = args.markdown && std::io::stdout().is_terminal();
+    loop {
+        eprint!("> ");
+        std::io::stderr().flush()?;
+        let mut line = String::new();
+        if stdin.read_line(&mut line)? == 0 {
+            eprintln!();
+            return Ok(None);
+        }
+        let line = line.trim();
+
+        match line.split_once(' ').unwrap_or((line, "")) {
+            ("", _) => continue,
+            ("/exit" | "/quit", _) => return Ok(None),
+            ("/clear", _) => {
+                conversation.clear();
+                if let Some(file) = session_file {
+                    save_session(file, conversation);
+                }
+                eprintln!("{}", t!("chat_cleared"));
+                continue;
+            },
+            ("/model", "") => {
+                eprintln!("{}", client.model());
+                continue;
+            },
+            ("/model", name) => return Ok(Some(name.trim().to_string())),
+            (command, _) if command.starts_with('/') => {
+                eprintln!("{}", t!("chat_unknown_command", command = command));
+                continue;
+            },
+            _ => {},
+        }
+
+        // The system prompt goes once, ahead of the conversation, unless --resend-system asks to
+        // repeat it at the top of every user message
+        let resend = |message: &str| match client.system_prompt() {
+            system if args.resend_system && !system.is_empty() => format!("{}\n\n{}", system, message),
+            _ => message.to_string(),
+        };
+        let turns = conversation.iter().map(|message| match message.role.as_str() {
+            "user" => config::Message { content: resend(&message.content), ..message.clone() },
+            _ => message.clone(),
+        });
+        let history = examples.iter().cloned().chain(turns).collect();
+        let prompt = resend(line);
+        client.set_history(history);
+        // A failed turn is reported and left out of the conversation, which goes on
+        let completion = match complete_with_refusal_retries(client, &prompt, config, args.retry_on_refusal, args.auto_continue) {
+            Ok(completion) => completion,
+            Err(err) => {
+                eprintln!("{}", t!("chat_turn_failed", error = format!("{:#}", err)));
+                continue;
+            },
+        };
+
+        if !args.nothink
+            && let Some(thought) = &completion.thinking {
+                if markdown {
+                    println!("<think>\n{}\n</think>", render_markdown(thought));
+                } else {
+                    println!("<think>\n{}\n</think>", thought);
+                }
+        }
+        let response = match args.wrap {
+            Some(cols) => wrap_text(&completion.text, wrap_width(cols)),
+            None => completion.text.clone(),
+        };
+        if markdown {
+            println!("{}", render_markdown(&response));
+        } else {
+            println!("{}", response);
+        }
+        if args.usage {
+            report_usage(config, client.model(), completion.usage);
+        }
+
+        conversation.push(config::Message::user(line));
+        conversation.push(config::Message::assistant(&history_answer(&completion, args.keep_thinking || config.keep_thinking)));
+        if let Some(file) = session_file {
+            save_session(file, conversation);
+        }
+    }
+}
+
+/// Runs --count: asks the same question `count` times with the same client, `--parallel` requests
+/// at a time. Each answer is printed under a "--- n/count ---" line, or with --json the output is
+/// an array of the objects a single query would print. Failed attempts are reported and make the process exit with an error.
+fn run_samples(config: &Config, args: &Args, client: &llm::Client, prompt: &str, count: u32) -> Result<()> {
+    let threads = args.parallel.unwrap_or(1).min(count);
+    let next = AtomicUsize::new(0);
+    let results: Mutex<Vec<Option<Result<Completion>>>> = Mutex::new((0..count).map(|_| None).collect());
+    thread::scope(|scope| {
+        for _ in 0..threads {
+            scope.spawn(|| loop {
+                let index = next.fetch_add(1, Ordering::Relaxed);
+                if index >= count as usize {
+                    break;
+                }
+                let result = complete_with_refusal_retries(client, prompt, config, args.retry_on_refusal, args.auto_continue);
+                results.lock().unwrap()[index] = Some(result);
+            });
+        }
+    });
+
+    let answer_json = |response: Option<&str>, think: Option<&str>, usage: Option<Usage>, error: Option<&str>| serde_json::json!({
+        "service": client.service_name(),
+        "model": client.model(),
+        "system_prompt": client.system_prompt(),
+        "prompt": prompt,
+        "response": response,
+        "think": think,
+        "usage": usage_json(config, client.model(), usage),
+        "error": error,
+        "tag": args.tag
+    });
+
+    let mut failed = 0;
+    let mut usage = None;
+    let mut answers = Vec::new();
+    for (index, result) in results.into_inner().unwrap().into_iter().flatten().enumerate() {
+        let completion = match result {
+            Ok(completion) => completion,
+            Err(err) => {
+                failed += 1;
+                let error = format!("{:#}", err);
+                eprintln!("{}", t!("sample_failed", index = index + 1, error = error));
+                answers.push(answer_json(None, None, None, Some(&error)));
+                continue;
+            },
+        };
+        usage = add_usage(usage, completion.usage);
+        if args.json {
+            answers.push(answer_json(Some(&completion.text), completion.thinking.as_deref(), completion.usage, None));
+            continue;
+        }
+        println!("--- {}/{} ---", index + 1, count);
+        if !args.nothink
+            && let Some(thought) = &completion.thinking {
+                println!("<think>\n{}\n</think>", thought);
+        }
+        match args.wrap {
+            Some(cols) => println!("{}", wrap_text(&completion.text, wrap_width(cols))),
+            None => println!("{}", completion.text),
+        }
+    }
+
+    if args.json {
+        println!("{}", serde_json::Value::Array(answers));
+    }
+    if args.usage {
+        report_usage(config, client.model(), usage);
+    }
+    if failed > 0 {
+        anyhow::bail!("{}", t!("samples_failed", count = failed, total = count));
+    }
+    Ok(())
+}
+
+/// Reads a JSON schema for --schema, checking it is a JSON object
+fn load_schema(path: &str) -> Result<serde_json::Value> {
+    let text = std::fs::read_to_string(path).with_context(|| t!("schema_unreadable", path = path))?;
+    let schema: serde_json::Value = serde_json::from_str(&text).with_context(|| t!("schema_invalid", path = path))?;
+    if !schema.is_object() {
+        anyhow::bail!("{}", t!("schema_invalid", path = path));
+    }
+    Ok(schema)
+}
+
+/// Applies the command-line settings of the query to a new client: the few-shot examples (as its
+/// history), the prefill, prompt caching, the system prompt's variables and additions, and the
+/// request parameters
+fn configure_client(client: &mut llm::Client, args: &Args, config: &Config) -> Result<()> {
+    if let Some(examples_name) = &args.examples {
+        client.set_history(config.example_set(examples_name)?.to_vec());
+    }
+    if let Some(prefill) = &args.prefill {
+        client.set_prefill(prefill);
+    }
+    if args.cache_prompt {
+        client.set_prompt_caching();
+    }
+    fill_system_vars(client, &args.var, args.strict || args.strict_vars)?;
+    if let Some(text) = &args.append_system {
+        client.append_system(text);
+    }
+    if let Some(words) = args.max_words {
+        client.set_max_words(words);
+    }
+    if let Some(max_tokens) = args.max_tokens {
+        client.set_max_tokens(max_tokens);
+    }
+    if let Some(temperature) = args.temperature {
+        client.set_temperature(temperature);
+    }
+    if !args.stop.is_empty() {
+        client.set_stop(&args.stop);
+    }
+    if let Some(path) = &args.schema {
+        client.set_schema(&load_schema(path)?);
+    }
+    Ok(())
+}
+
+/// Sends the query with `client` from a worker thread and waits at most `limit` for the answer,
+/// which goes through the same refusal retries, continuation and logging as any other. Returns the
+/// client back with the result, or None when the limit expires; the abandoned worker dies with the process.
+fn complete_with_soft_timeout(client: llm::Client, config: &Config, args: &Args, prompt: &str, limit: Duration) -> Option<(llm::Client, Result<Completion>)> {
+    let (tx, rx) = mpsc::channel();
+    let config = config.clone();
+    let (retries, auto_continue) = (args.retry_on_refusal, args.auto_continue);
+    let prompt = prompt.to_string();
+
+    thread::spawn(move || {
+        let result = complete_with_refusal_retries(&client, &prompt, &config, retries, auto_continue);
+        let _ = tx.send((client, result));
+    });
+
+    rx.recv_timeout(limit).ok()
+}
+
+/// Fills `{name}` placeholders in an output template. `{{` and `}}` produce literal braces.
+fn render_output_template(template: &str, values: &[(&str, &str)]) -> Result<String> {
+    const PLACEHOLDERS: &[&str] = &["service", "model", "prompt", "think", "response"];
+
+    let mut output = String::new();
+    let mut rest = template;
+    while let Some(pos) = rest.find(['{', '}']) {
+        output.push_str(&rest[..pos]);
+        let tail = &rest[pos..];
+        if tail.starts_with("{{") || tail.starts_with("}}") {
+            output.push_str(&tail[..1]);
+            rest = &tail[2..];
+        } else if tail.starts_with('}') {
+            anyhow::bail!("{}", t!("template_unmatched_brace", template = template));
+        } else {
+            let end = tail.find('}').with_context(|| t!("template_unmatched_brace", template = template))?;
+            let name = &tail[1..end];
+            if !PLACEHOLDERS.contains(&name) {
+                anyhow::bail!("{}", t!("template_unknown_placeholder", name = name, valid = PLACEHOLDERS.join(", ")));
+            }
+            let value = values.iter().find(|(key, _)| *key == name).map(|(_, v)| *v).unwrap_or("");
+            output.push_str(value);
+            rest = &tail[end + 1..];
+        }
+    }
+    output.push_str(rest);
+    Ok(output)
+}
+
+/// Column count for --wrap: 0 means the terminal width (80 when it can't be detected)
+fn wrap_width(cols: usize) -> usize {
+    if cols > 0 {
+        return cols;
+    }
+    terminal_size::terminal_size().map(|(terminal_size::Width(w), _)| w as usize).unwrap_or(80)
+}
+
+/// Word-wraps text to `width` columns, keeping each line's indentation.
+/// Code fences are left untouched, and words longer than the width are not split.
+fn wrap_text(text: &str, width: usize) -> String {
+    let mut output = Vec::new();
+    let mut in_fence = false;
+    for line in text.lines() {
+        if line.trim_start().starts_with("```") {
+            in_fence = !in_fence;
+            output.push(line.to_string());
+            continue;
+        }
+        if in_fence || line.chars().count() <= width {
+            output.push(line.to_string());
+            continue;
+        }
+
+        let indent = &line[..line.len() - line.trim_start().len()];
+        let mut current = indent.to_string();
+        for word in line.split_whitespace() {
+            let current_len = current.chars().count();
+            if current_len > indent.len() && current_len + 1 + word.chars().count() > width {
+                output.push(std::mem::replace(&mut current, indent.to_string()));
+            }
+            if current.len() > indent.len() {
+                current.push(' ');
+            }
+            current.push_str(word);
+        }
+        output.push(current);
+    }
+    output.join("\n")
+}
+
+/// Styles markdown for the terminal: headers, lists, quotes, rules, bold, italics and code, with
+/// fenced code highlighted for the languages `highlight` knows. Anything else is printed as written.
+fn render_markdown(text: &str) -> String {
+    let bold = Regex::new(r"\*\*(.+?)\*\*|__(.+?)__").unwrap();
+    let italic = Regex::new(r"\*([^*\s](?:[^*]*[^*\s])?)\*").unwrap();
+    let list_item = Regex::new(r"^(\s*)[-*+] (.*)$").unwrap();
+    let emphasis = |text: &str| {
+        let text = bold.replace_all(text, "\x1b[1m$1$2\x1b[22m");
+        italic.replace_all(&text, "\x1b[3m$1\x1b[23m").into_owned()
+    };
+    // Text between backticks is code and gets no other styling
+    let inline = |line: &str| -> String {
+        if line.matches('`').count() % 2 == 1 {
+            return emphasis(line);
+        }
+        line.split('`').enumerate()
+            .map(|(i, part)| if i % 2 == 1 { format!("\x1b[36m{}\x1b[39m", part) } else { emphasis(part) })
+            .collect()
+    };
+
+    let mut output = Vec::new();
+    let mut in_fence = false;
+    // Code in a language we know is highlighted, the rest is set apart in one color
+    let mut syntax = None;
+    for line in text.lines() {
+        let trimmed = line.trim_start();
+        if let Some(language) = trimmed.strip_prefix("```") {
+            in_fence = !in_fence;
+            syntax = None;
+            if in_fence && !language.trim().is_empty() {
+                syntax = highlight::syntax(language.trim());
+                output.push(format!("\x1b[2m{}\x1b[0m", language.trim()));
+            }
+            continue;
+        }
+        if in_fence {
+            match syntax {
+                Some(syntax) => output.push(format!("  {}", highlight::highlight(line, syntax))),
+                None => output.push(format!("  \x1b[36m{}\x1b[39m", line)),
+            }
+            continue;
+        }
+
+        let hashes = trimmed.chars().take_while(|&c| c == '#').count();
+        if (1..=6).contains(&hashes) && trimmed[hashes..].starts_with(' ') {
+            let title = inline(trimmed[hashes..].trim());
+            output.push(if hashes == 1 { format!("\x1b[1;4m{}\x1b[0m", title) } else { format!("\x1b[1m{}\x1b[0m", title) });
+        } else if ["---", "***", "___"].contains(&trimmed.trim_end()) {
+            output.push(format!("\x1b[2m{}\x1b[0m", "─".repeat(wrap_width(0).min(80))));
+        } else if let Some(quote) = trimmed.strip_prefix('>') {
+            output.push(format!("\x1b[2m│\x1b[0m {}", inline(quote.trim_start())));
+        } else if let Some(item) = list_item.captures(line) {
+            output.push(format!("{}• {}", &item[1], inline(&item[2])));
+        } else {
+            output.push(inline(line));
+        }
+    }
+    output.join("\n")
+}
+
+/// A price in USD with no more decimals than it needs: 3, 0.15, 0.0375
+fn format_price(price: f64) -> String {
+    format!("{}", (price * 10_000.0).round() / 10_000.0)
+}
+
+/// Token counts for --json output, with the estimated cost when the model has a price. Null when
+/// the service didn't report them.
+fn usage_json(config: &Config, model: &str, usage: Option<Usage>) -> serde_json::Value {
+    let Some(usage) = usage else {
+        return serde_json::Value::Null;
+    };
+    let mut value = serde_json::json!({
+        "input": usage.input,
+        "output": usage.output,
+        "total": usage.total(),
+        "cache_read": usage.cache_read,
+        "cache_write": usage.cache_write,
+    });
+    if let Some(cost) = config.estimate_cost(model, usage.input, usage.output) {
+        value["cost"] = serde_json::json!(cost);
+    }
+    value
+}
+
+/// Appends a query and its answer to the config's `log_file` (or --log-file) as one JSON line.
+/// Only the service and model names go in, never their settings, so API keys can't leak into
+/// the log. A failed write is a warning: the answer matters more than its record.
+fn log_query(config: &Config, client: &llm::Client, prompt: &str, completion: &Completion) {
+    let Some(path) = &config.log_file else {
+        return;
+    };
+    let timestamp = std::time::SystemTime::now().duration_since(std::time::UNIX_EPOCH).map(|d| d.as_secs()).unwrap_or(0);
+    let entry = serde_json::json!({
+        "timestamp": timestamp,
+        "service": client.service_name(),
+        "model": client.model(),
+        "prompt": prompt,
+        "response": completion.text,
+        "usage": completion.usage,
+        "tag": config.tag,
+    });
+    // One write per entry, so concurrent queries (--parallel, several askme runs) don't interleave lines
+    let result = std::fs::OpenOptions::new().create(true).append(true).open(path)
+        .and_then(|mut file| file.write_all(format!("{}\n", entry).as_bytes()));
+    if let Err(err) = result {
+        eprintln!("{}", t!("failed_write_log", path = path, error = err));
+    }
+}
+
+/// Prints the tokens a query used for --usage, on stderr so the answer stays alone on stdout
+fn report_usage(config: &Config, model: &str, usage: Option<Usage>) {
+    let Some(usage) = usage else {
+        eprintln!("{}", t!("usage_unavailable"));
+        return;
+    };
+    match config.estimate_cost(model, usage.input, usage.output) {
+        Some(cost) => eprintln!("{}", t!("usage_tokens_cost", input = usage.input, output = usage.output, total = usage.total(), cost = format!("{:.6}", cost))),
+        None => eprintln!("{}", t!("usage_tokens", input = usage.input, output = usage.output, total = usage.total())),
+    }
+    if usage.cache_read > 0 || usage.cache_write > 0 {
+        eprintln!("{}", t!("usage_prompt_cache", read = usage.cache_read, written = usage.cache_write));
+    }
+}
+
+fn print_thinking_panel(thought: &str, markdown: bool) {
+    // Dimmed, indented block so the reasoning is visually apart from the answer
+    if markdown {
+        // Rendered styles would undo the dimming, so only the frame is dimmed
+        for line in render_markdown(thought).lines() {
+            println!("\x1b[2m  │\x1b[0m {}", line);
+        }
+    } else {
+        for line in thought.lines() {
+            println!("\x1b[2m  │ {}\x1b[0m", line);
+        }
+    }
+    println!();
+}
+
+/// Asks every --vote service and reports the most common answer (or the value at --json-path
+/// of each answer's JSON), with what each service said. Ties are reported, not broken.
+fn run_vote(config: &Config, args: &Args, services: &[String], prompt: &str, images: &[config::ImageInput], lang: Option<&str>) -> Result<()> {
+    let answers = ask_services(config, args, services, prompt, images, lang);
+
+    // Each service's ballot: its answer, or the value found at --json-path
+    let ballots: Vec<Option<serde_json::Value>> = answers.iter().map(|answer| {
+        let response = answer.response.as_deref()?;
+        match &args.json_path {
+            Some(path) => {
+                let data = extract_json_blocks(response).or_else(|| serde_json::from_str(response.trim()).ok());
+                let value = data.as_ref().and_then(|data| lookup_json_path(data, path)).cloned();
+                if value.is_none() {
+                    eprintln!("{}", t!("vote_path_not_found", service = answer.service, path = path));
+                }
+                value
+            },
+            None => Some(serde_json::Value::String(response.trim().to_string())),
+        }
+    }).collect();
+
+    // Count votes, keeping values in the order they first appeared
+    let mut tally: Vec<(&serde_json::Value, usize)> = Vec::new();
+    for value in ballots.iter().flatten() {
+        match tally.iter_mut().find(|(seen, _)| *seen == value) {
+            Some((_, count)) => *count += 1,
+            None => tally.push((value, 1)),
+        }
+    }
+    let Some(top) = tally.iter().map(|(_, count)| *count).max() else {
+        anyhow::bail!("{}", t!("vote_no_ballots"));
+    };
+    let leaders: Vec<&serde_json::Value> = tally.iter().filter(|(_, count)| *count == top).map(|(value, _)| *value).collect();
+    let winner = if leaders.len() == 1 { Some(leaders[0]) } else { None };
+    let display = |value: &serde_json::Value| match value {
+        serde_json::Value::String(text) => text.clone(),
+        other => other.to_string(),
+    };
+
+    if args.json {
+        let breakdown: Vec<serde_json::Value> = answers.iter().zip(&ballots).map(|(answer, value)| serde_json::json!({
+            "service": answer.service,
+            "model": answer.model,
+            "value": value,
+            "response": answer.response,
+            "error": answer.error,
+        })).collect();
+        println!("{}", serde_json::json!({
+            "prompt": prompt,
+            "json_path": args.json_path,
+            "winner": winner,
+            "tie": winner.is_none(),
+            "leaders": leaders,
+            "votes": top,
+            "voters": answers.len(),
+            "breakdown": breakdown,
+            "tag": args.tag,
+        }));
+    } else {
+        match winner {
+            Some(value) => println!("{}", t!("vote_winner", value = display(value), votes = top, voters = answers.len())),
+            None => println!("{}", t!("vote_tie", values = leaders.iter().map(|value| display(value)).collect::<Vec<_>>().join(", "), votes = top)),
+        }
+        for (answer, value) in answers.iter().zip(&ballots) {
+            let model = answer.model.as_deref().unwrap_or("-");
+            let value = value.as_ref().map(display).unwrap_or_else(|| t!("vote_no_value").to_string());
+            println!("- {} ({}): {}", answer.service, model, value);
+        }
+    }
+    Ok(())
+}
+
+/// Follows a dotted path (array items by index, as printed by --flatten) into a JSON value
+fn lookup_json_path<'v>(value: &'v serde_json::Value, path: &str) -> Option<&'v serde_json::Value> {
+    path.split('.').filter(|key| !key.is_empty()).try_fold(value, |current, key| match current {
+        serde_json::Value::Object(map) => map.get(key),
+        serde_json::Value::Array(items) => key.parse::<usize>().ok().and_then(|index| items.get(index)),
+        _ => None,
+    })
+}
+
+/// Flattens nested JSON into an object mapping dotted paths (array items by index) to scalar values.
+/// Empty objects and arrays are kept as values so nothing is lost.
+fn flatten_json(value: &serde_json::Value) -> serde_json::Value {
+    fn walk(value: &serde_json::Value, path: String, flat: &mut serde_json::Map<String, serde_json::Value>) {
+        let join = |key: &str| if path.is_empty() { key.to_string() } else { format!("{}.{}", path, key) };
+        match value {
+            serde_json::Value::Object(map) if !map.is_empty() => {
+                for (key, item) in map {
+                    walk(item, join(key), flat);
+                }
+            },
+            serde_json::Value::Array(items) if !items.is_empty() => {
+                for (index, item) in items.iter().enumerate() {
+                    walk(item, join(&index.to_string()), flat);
+                }
+            },
+            leaf => {
+                flat.insert(path, leaf.clone());
+            },
+        }
+    }
+
+    let mut flat = serde_json::Map::new();
+    walk(value, String::new(), &mut flat);
+    serde_json::Value::Object(flat)
+}
+
+/// Finds the balanced `{...}` and `[...]` spans of a text that parse as JSON, outermost first.
+/// Brackets inside string literals don't count.
+fn bare_json_spans(text: &str) -> Vec<serde_json::Value> {
+    let bytes = text.as_bytes();
+    let mut values = Vec::new();
+    let mut start = 0;
+    while let Some(offset) = bytes[start..].iter().position(|&b| b == b'{' || b == b'[') {
+        let open = start + offset;
+        let mut depth = 0;
+        let mut in_string = false;
+        let mut escaped = false;
+        let mut close = None;
+        for (index, &byte) in bytes.iter().enumerate().skip(open) {
+            if in_string {
+                match byte {
+                    _ if escaped => escaped = false,
+                    b'\\' => escaped = true,
+                    b'"' => in_string = false,
+                    _ => {},
+                }
+                continue;
+            }
+            match byte {
+                b'"' => in_string = true,
+                b'{' | b'[' => depth += 1,
+                b'}' | b']' => {
+                    depth -= 1;
+                    if depth == 0 {
+                        close = Some(index);
+                        break;
+                    }
+                },
+                _ => {},
+            }
+        }
+        // Brackets are ASCII, so the span starts and ends on character boundaries
+        match close.and_then(|close| serde_json::from_str(&text[open..=close]).ok().map(|value| (close, value))) {
+            Some((close, value)) => {
+                values.push(value);
+                start = close + 1;
+            },
+            None => start = open + 1,
+        }
+    }
+    values
+}
+
 fn extract_json_blocks(response: &str) -> Option<serde_json::Value> {
     // Regex to find ```json ... ``` blocks
     // Dot matches newline needs to be enabled for content
@@ -377,6 +2566,15 @@ fn extract_json_blocks(response: &str) -> Option<serde_json::Value> {
         }
     }
 
+    // Many models answer with bare JSON, without any fences
+    if blocks.is_empty()
+        && let Ok(val) = serde_json::from_str::<serde_json::Value>(response.trim()) {
+            blocks.push(val);
+    }
+    if blocks.is_empty() {
+        blocks = bare_json_spans(response);
+    }
+
     if blocks.is_empty() {
         None
     } else if blocks.len() == 1 {
@@ -0,0 +1,173 @@
+use crate::config::Config;
+use crate::drivers::VALID_CLASSES;
+use anyhow::{Context, Result, bail};
+use regex::Regex;
+use rust_i18n::t;
+use serde_yaml::{Mapping, Value};
+use std::path::{Path, PathBuf};
+
+/// Result of upgrading a configuration file
+pub struct Migration {
+    /// Upgraded YAML, if anything had to change
+    pub yaml: Option<String>,
+    /// What was changed, in order
+    pub changes: Vec<String>,
+    /// Problems found that need a human (e.g. unknown fields)
+    pub warnings: Vec<String>,
+}
+
+/// Applies the known transformations to a configuration file's contents.
+/// Works on the raw YAML so fields askme doesn't know about are kept untouched.
+pub fn migrate(contents: &str) -> Result<Migration> {
+    let mut root: Value = serde_yaml::from_str(contents)?;
+    let (top_fields, service_fields) = Config::known_fields();
+    let mut changes = Vec::new();
+    let mut warnings = Vec::new();
+
+    let Some(map) = root.as_mapping_mut() else {
+        return Ok(Migration { yaml: None, changes, warnings });
+    };
+
+    // "x-" keys are free for YAML anchors
+    for key in map.keys().filter_map(Value::as_str).filter(|key| !key.starts_with("x-")) {
+        if !top_fields.iter().any(|field| field == key) {
+            warnings.push(t!("migrate_unknown_field", field = key).to_string());
+        }
+    }
+
+    if let Some(services) = map.get_mut("services").and_then(Value::as_mapping_mut) {
+        for (name, service) in services.iter_mut() {
+            let name = name.as_str().unwrap_or_default();
+            let Some(service) = service.as_mapping_mut() else {
+                continue;
+            };
+
+            // "chat_path" is accepted as another name for "completions_path"
+            if !service.contains_key("completions_path")
+                && let Some(path) = service.remove("chat_path") {
+                    service.insert(Value::from("completions_path"), path);
+                    changes.push(t!("migrate_field_renamed", service = name, from = "chat_path", to = "completions_path").to_string());
+            }
+
+            // "<<" is a YAML merge key, expanded when the file is loaded
+            for key in service.keys().filter_map(Value::as_str).filter(|key| *key != "<<") {
+                if !service_fields.iter().any(|field| field == key) {
+                    warnings.push(t!("migrate_unknown_service_field", service = name, field = key).to_string());
+                }
+            }
+
+            // Classes are matched exactly, so "OpenAI" or " ollama" never worked
+            if let Some(Value::String(class)) = service.get_mut("class") {
+                let normalized = class.trim().to_lowercase();
+                if normalized != *class && VALID_CLASSES.contains(&normalized.as_str()) {
+                    changes.push(t!("migrate_class_normalized", service = name, from = class, to = normalized).to_string());
+                    *class = normalized;
+                }
+            }
+        }
+    }
+
+    for field in ["url", "api_key"] {
+        move_shared_to_defaults(map, field, &mut changes);
+    }
+
+    let yaml = if changes.is_empty() { None } else { Some(serde_yaml::to_string(&root)?) };
+    Ok(Migration { yaml, changes, warnings })
+}
+
+/// Moves the value of `field` most services repeat into the top-level `defaults`. Services with
+/// another value keep theirs. Nothing moves unless every service gets the field from itself or
+/// through `extends`, since the others would pick up the default.
+fn move_shared_to_defaults(map: &mut Mapping, field: &str, changes: &mut Vec<String>) {
+    let Some(services) = map.get("services").and_then(Value::as_mapping) else {
+        return;
+    };
+    if map.get("defaults").is_some_and(|defaults| !defaults.is_mapping() || defaults.get(field).is_some()) {
+        return;
+    }
+
+    let mut counts: Vec<(&Value, usize)> = Vec::new();
+    for service in services.values() {
+        // Following `extends` (with a bound, in case of a cycle) to the service that sets it
+        let mut current = Some(service);
+        let mut steps = 0;
+        while let Some(service) = current.filter(|service| service.get(field).is_none() && steps < services.len()) {
+            current = service.get("extends").and_then(|parent| services.get(parent));
+            steps += 1;
+        }
+        let Some(value) = current.and_then(|service| service.get(field)) else {
+            return;
+        };
+        if service.get(field).is_some() {
+            match counts.iter_mut().find(|(counted, _)| *counted == value) {
+                Some((_, count)) => *count += 1,
+                None => counts.push((value, 1)),
+            }
+        }
+    }
+    // The first of the most repeated values, so the result doesn't depend on hashing
+    let Some((value, count)) = counts.into_iter().rev().max_by_key(|(_, count)| *count).filter(|(_, count)| *count > 1) else {
+        return;
+    };
+    let value = value.clone();
+
+    if let Some(services) = map.get_mut("services").and_then(Value::as_mapping_mut) {
+        for service in services.values_mut().filter_map(Value::as_mapping_mut) {
+            if service.get(field) == Some(&value) {
+                service.remove(field);
+            }
+        }
+    }
+    let defaults = map.entry(Value::from("defaults")).or_insert_with(|| Value::Mapping(Mapping::new()));
+    if let Some(defaults) = defaults.as_mapping_mut() {
+        defaults.insert(Value::from(field), value);
+    }
+    changes.push(t!("migrate_moved_to_defaults", field = field, count = count).to_string());
+}
+
+/// Sets `default_service` in a configuration file's contents. Only that line is edited (or added
+/// at the top), so comments and formatting are kept.
+pub fn set_default_service(contents: &str, service: &str) -> Result<String> {
+    let value = serde_yaml::to_string(service)?.trim_end().to_string();
+    let line_pattern = Regex::new(r#"^default_service:\s*(?:"(?:[^"\\]|\\.)*"|'[^']*'|[^#]*?)\s*(#.*)?$"#).unwrap();
+
+    let mut found = false;
+    let mut updated = String::with_capacity(contents.len() + value.len());
+    for line in contents.split_inclusive('\n') {
+        let text = line.trim_end_matches(['\r', '\n']);
+        if !found && let Some(caps) = line_pattern.captures(text) {
+            found = true;
+            updated.push_str("default_service: ");
+            updated.push_str(&value);
+            if let Some(comment) = caps.get(1) {
+                updated.push(' ');
+                updated.push_str(comment.as_str());
+            }
+            updated.push_str(&line[text.len()..]);
+        } else {
+            updated.push_str(line);
+        }
+    }
+    if !found {
+        updated = format!("default_service: {}\n{}", value, updated);
+    }
+
+    // A value spread over several lines would have been cut; better to refuse than to break the file
+    let check: Value = serde_yaml::from_str(&updated)?;
+    if check.get("default_service").and_then(Value::as_str) != Some(service) {
+        bail!("{}", t!("set_default_unsupported"));
+    }
+    Ok(updated)
+}
+
+/// Writes the upgraded configuration over `path`, keeping the original as `<path>.bak`.
+/// Returns the backup path.
+pub fn write_with_backup(path: &Path, yaml: &str) -> Result<PathBuf> {
+    let mut backup = path.as_os_str().to_owned();
+    backup.push(".bak");
+    let backup = PathBuf::from(backup);
+
+    std::fs::copy(path, &backup).with_context(|| t!("migrate_backup_failed", path = backup.display()))?;
+    std::fs::write(path, yaml).with_context(|| t!("migrate_write_failed", path = path.display()))?;
+    Ok(backup)
+}
@@ -0,0 +1,85 @@
+use anyhow::{Context, Result};
+use serde::{Serialize, de::DeserializeOwned};
+use std::{fs, path::{Path, PathBuf}};
+
+/// Directory holding askme's per-user state files (e.g. ~/.cache/askme)
+pub fn state_dir() -> Result<PathBuf> {
+    let dir = dirs::cache_dir().context("Unable to determine the user cache directory")?;
+    Ok(dir.join("askme"))
+}
+
+/// Directory holding the conversations of --session and --continue (e.g. ~/.config/askme).
+/// Unlike the state directory it is no cache, so cleaning caches doesn't lose them.
+pub fn sessions_dir() -> Result<PathBuf> {
+    let dir = dirs::config_dir().context("Unable to determine the user config directory")?;
+    Ok(dir.join("askme"))
+}
+
+/// Stores a value as JSON under the state directory
+pub fn save<T: Serialize>(name: &str, value: &T) -> Result<()> {
+    save_at(&state_dir()?.join(name), value)
+}
+
+/// Loads a value previously stored with `save`. Returns None if it was never saved.
+pub fn load<T: DeserializeOwned>(name: &str) -> Result<Option<T>> {
+    load_at(&state_dir()?.join(name))
+}
+
+/// Deletes a value stored with `save`. Nothing happens if it was never saved.
+pub fn remove(name: &str) -> Result<()> {
+    remove_at(&state_dir()?.join(name))
+}
+
+/// Deletes a subdirectory of the state directory and everything in it. Nothing happens if it doesn't exist.
+pub fn remove_dir(name: &str) -> Result<()> {
+    let path = state_dir()?.join(name);
+    match fs::remove_dir_all(&path) {
+        Err(err) if err.kind() != std::io::ErrorKind::NotFound => Err(err).with_context(|| format!("Failed to delete {:?}", path)),
+        _ => Ok(()),
+    }
+}
+
+/// Stores a conversation as JSON under the sessions directory
+pub fn save_session<T: Serialize>(name: &str, value: &T) -> Result<()> {
+    save_at(&sessions_dir()?.join(name), value)
+}
+
+/// Loads a conversation stored with `save_session`. One saved before sessions moved out of the
+/// state directory is still found there, and is saved to the sessions directory next time.
+pub fn load_session<T: DeserializeOwned>(name: &str) -> Result<Option<T>> {
+    match load_at(&sessions_dir()?.join(name))? {
+        Some(value) => Ok(Some(value)),
+        None => load(name),
+    }
+}
+
+/// Deletes a conversation stored with `save_session`, along with any copy left in the state directory
+pub fn remove_session(name: &str) -> Result<()> {
+    remove_at(&sessions_dir()?.join(name))?;
+    remove(name)
+}
+
+fn save_at<T: Serialize>(path: &Path, value: &T) -> Result<()> {
+    // The name may include a subdirectory (e.g. "responses/<hash>.json")
+    let dir = path.parent().context("Invalid state file name")?;
+    fs::create_dir_all(dir).with_context(|| format!("Failed to create {:?}", dir))?;
+    let json = serde_json::to_string_pretty(value)?;
+    fs::write(path, json).with_context(|| format!("Failed to write {:?}", path))?;
+    Ok(())
+}
+
+fn load_at<T: DeserializeOwned>(path: &Path) -> Result<Option<T>> {
+    if !path.exists() {
+        return Ok(None);
+    }
+    let json = fs::read_to_string(path).with_context(|| format!("Failed to read {:?}", path))?;
+    let value = serde_json::from_str(&json).with_context(|| format!("Failed to parse {:?}", path))?;
+    Ok(Some(value))
+}
+
+fn remove_at(path: &Path) -> Result<()> {
+    match fs::remove_file(path) {
+        Err(err) if err.kind() != std::io::ErrorKind::NotFound => Err(err).with_context(|| format!("Failed to delete {:?}", path)),
+        _ => Ok(()),
+    }
+}
@@ -0,0 +1,22 @@
+//! A service's body_template is filled in one pass, so placeholders in the prompt stay as written.
+
+mod common;
+
+use common::{askme, serve_completions, temp_dir, write_config};
+
+#[test]
+fn placeholders_inside_the_prompt_are_not_filled() {
+    let dir = temp_dir("body_template");
+    let (url, requests) = serve_completions("pong");
+    let template = "    body_template: '{\"model\": {{model}}, \"input\": {{prompt}}, \"messages\": {{messages}}}'\n";
+    write_config(&dir, &url, template);
+
+    let output = askme(&dir, &["say {{messages}} and {{model}}"]);
+    assert!(output.status.success(), "{}", String::from_utf8_lossy(&output.stderr));
+    std::fs::remove_dir_all(&dir).ok();
+
+    let body = &requests.lock().unwrap()[0];
+    assert_eq!(body["model"], "test-model");
+    assert_eq!(body["input"], "say {{messages}} and {{model}}");
+    assert_eq!(body["messages"][0]["content"], "say {{messages}} and {{model}}");
+}
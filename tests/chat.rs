@@ -0,0 +1,40 @@
+//! --chat sends the system prompt once, ahead of the conversation, unless --resend-system asks
+//! for it with every message.
+
+mod common;
+
+use common::{askme_with_stdin, serve_completions, temp_dir, write_config};
+
+/// The user messages of the second turn of a two-line chat
+fn second_turn_user_messages(resend: bool) -> Vec<String> {
+    let dir = temp_dir(if resend { "chat_resend" } else { "chat_once" });
+    let (url, requests) = serve_completions("pong");
+    write_config(&dir, &url, "");
+
+    let mut args = vec!["--chat"];
+    if resend {
+        args.push("--resend-system");
+    }
+    let output = askme_with_stdin(&dir, &args, "ping\nagain\n");
+    assert!(output.status.success(), "{}", String::from_utf8_lossy(&output.stderr));
+    std::fs::remove_dir_all(&dir).ok();
+
+    let requests = requests.lock().unwrap();
+    assert_eq!(requests.len(), 2);
+    let messages = requests[1]["messages"].as_array().unwrap();
+    assert_eq!(messages.iter().filter(|m| m["role"] == "system").count(), 1);
+    messages.iter()
+        .filter(|m| m["role"] == "user")
+        .map(|m| m["content"].as_str().unwrap().to_string())
+        .collect()
+}
+
+#[test]
+fn system_prompt_is_sent_once() {
+    assert_eq!(second_turn_user_messages(false), ["ping", "again"]);
+}
+
+#[test]
+fn resend_system_repeats_it_with_every_message() {
+    assert_eq!(second_turn_user_messages(true), ["Be brief.\n\nping", "Be brief.\n\nagain"]);
+}
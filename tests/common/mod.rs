@@ -0,0 +1,129 @@
+//! Helpers shared by the integration tests: a fake service and a way to run askme against it
+// Each test file uses only some of them
+#![allow(dead_code)]
+
+use std::io::{BufRead, BufReader, Read, Write};
+use std::net::TcpListener;
+use std::path::PathBuf;
+use std::process::{Command, Output, Stdio};
+use std::sync::{Arc, Mutex};
+use std::thread;
+
+/// Request bodies received by a fake service, in order
+pub type Requests = Arc<Mutex<Vec<serde_json::Value>>>;
+
+/// Serves OpenAI-style chat completions answering `reply` to every request, and returns the
+/// server's base URL and the bodies it receives
+pub fn serve_completions(reply: &'static str) -> (String, Requests) {
+    serve_json(serde_json::json!({"choices": [{"message": {"content": reply}, "finish_reason": "stop"}]}))
+}
+
+/// Answers every request with `reply`, and returns the server's base URL and the bodies it receives
+pub fn serve_json(reply: serde_json::Value) -> (String, Requests) {
+    serve("200 OK", "", reply.to_string())
+}
+
+/// Answers every request with an empty JSON object under `status` (e.g. "429 Too Many Requests")
+/// and the extra header lines in `headers`, each ending in "\r\n"
+pub fn serve_status(status: &'static str, headers: &'static str) -> (String, Requests) {
+    serve(status, headers, "{}".to_string())
+}
+
+fn serve(status: &'static str, headers: &'static str, reply: String) -> (String, Requests) {
+    let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+    let url = format!("http://{}", listener.local_addr().unwrap());
+    let requests = Requests::default();
+
+    let received = requests.clone();
+    thread::spawn(move || {
+        for stream in listener.incoming() {
+            let mut reader = BufReader::new(stream.unwrap());
+            let mut content_length = 0;
+            loop {
+                let mut line = String::new();
+                reader.read_line(&mut line).unwrap();
+                if line == "\r\n" || line.is_empty() {
+                    break;
+                }
+                if let Some((name, value)) = line.split_once(':')
+                    && name.eq_ignore_ascii_case("content-length") {
+                        content_length = value.trim().parse().unwrap();
+                }
+            }
+            let mut body = vec![0; content_length];
+            reader.read_exact(&mut body).unwrap();
+            received.lock().unwrap().push(serde_json::from_slice(&body).unwrap_or_default());
+
+            let mut stream = reader.into_inner();
+            write!(stream, "HTTP/1.1 {}\r\n{}Content-Type: application/json\r\nContent-Length: {}\r\nConnection: close\r\n\r\n{}", status, headers, reply.len(), reply).unwrap();
+        }
+    });
+
+    (url, requests)
+}
+
+/// Accepts connections and never answers them. Returns the server's base URL.
+pub fn serve_nothing() -> String {
+    let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+    let url = format!("http://{}", listener.local_addr().unwrap());
+
+    thread::spawn(move || {
+        let mut open = Vec::new();
+        for stream in listener.incoming() {
+            open.push(stream);
+        }
+    });
+
+    url
+}
+
+/// A directory of its own for one test, created empty
+pub fn temp_dir(name: &str) -> PathBuf {
+    let dir = std::env::temp_dir().join(format!("askme-test-{}-{}", name, std::process::id()));
+    std::fs::remove_dir_all(&dir).ok();
+    std::fs::create_dir_all(&dir).unwrap();
+    dir
+}
+
+/// Writes a config in `dir` with a single OpenAI service `test` at `url`, followed by `extra`
+/// top-level YAML, and returns its path
+pub fn write_config(dir: &std::path::Path, url: &str, extra: &str) -> PathBuf {
+    let path = dir.join("askme.yml");
+    let config = format!(
+        "default_service: test\ndefault_prompt: basic\nsystem_prompts:\n  basic: \"Be brief.\"\nservices:\n  test:\n    class: openai\n    url: {}\n    api_key: sk-test\n    model: test-model\n{}",
+        url, extra
+    );
+    std::fs::write(&path, config).unwrap();
+    path
+}
+
+/// Runs askme with the config in `dir`, keeping its cache and config directories inside `dir`
+pub fn askme(dir: &std::path::Path, args: &[&str]) -> Output {
+    Command::new(env!("CARGO_BIN_EXE_askme"))
+        .env("XDG_CACHE_HOME", dir.join("cache"))
+        .env("XDG_CONFIG_HOME", dir.join("config"))
+        .env("NO_COLOR", "1")
+        .env("LC_ALL", "en_US.UTF-8")
+        .args(["--no-global-config", "-c", dir.join("askme.yml").to_str().unwrap()])
+        .args(args)
+        .output()
+        .unwrap()
+}
+
+/// Runs askme like `askme`, writing `input` to its stdin
+pub fn askme_with_stdin(dir: &std::path::Path, args: &[&str], input: &str) -> Output {
+    let mut child = Command::new(env!("CARGO_BIN_EXE_askme"))
+        .env("XDG_CACHE_HOME", dir.join("cache"))
+        .env("XDG_CONFIG_HOME", dir.join("config"))
+        .env("NO_COLOR", "1")
+        .env("LC_ALL", "en_US.UTF-8")
+        .args(["--no-global-config", "-c", dir.join("askme.yml").to_str().unwrap()])
+        .args(args)
+        .stdin(Stdio::piped())
+        .stdout(Stdio::piped())
+        .stderr(Stdio::piped())
+        .spawn()
+        .unwrap();
+    child.stdin.take().unwrap().write_all(input.as_bytes()).unwrap();
+    child.wait_with_output().unwrap()
+}
@@ -0,0 +1,28 @@
+//! `${NAME}` in a service is only expanded when that service is used, so an unset variable
+//! doesn't get in the way of the other services or of commands that send nothing.
+
+mod common;
+
+use common::{askme, serve_completions, temp_dir, write_config};
+
+#[test]
+fn unset_variables_only_break_their_service() {
+    let dir = temp_dir("env_vars");
+    let (url, _requests) = serve_completions("pong");
+    write_config(&dir, &url, "  other:\n    class: openai\n    url: http://127.0.0.1:1\n    api_key: ${ASKME_TEST_UNSET_KEY}\n    model: m\n");
+
+    let query = askme(&dir, &["ping"]);
+    let list = askme(&dir, &["--list"]);
+    let lint = askme(&dir, &["--lint"]);
+    let other = askme(&dir, &["-s", "other", "ping"]);
+    std::fs::remove_dir_all(&dir).ok();
+
+    assert!(query.status.success(), "{}", String::from_utf8_lossy(&query.stderr));
+    assert_eq!(String::from_utf8_lossy(&query.stdout).trim(), "pong");
+    assert!(list.status.success(), "{}", String::from_utf8_lossy(&list.stderr));
+    assert!(lint.status.success(), "{}", String::from_utf8_lossy(&lint.stderr));
+    let lint_output = String::from_utf8_lossy(&lint.stdout) + String::from_utf8_lossy(&lint.stderr);
+    assert!(lint_output.contains("ASKME_TEST_UNSET_KEY"), "{}", lint_output);
+    assert!(!other.status.success());
+    assert!(String::from_utf8_lossy(&other.stderr).contains("Environment variable 'ASKME_TEST_UNSET_KEY'"));
+}
@@ -0,0 +1,19 @@
+//! Fatal errors are reported with their causes and, when there is one, a hint on what to do.
+
+mod common;
+
+use common::{askme, serve_nothing, temp_dir, write_config};
+
+#[test]
+fn a_timeout_is_reported_with_its_hint() {
+    let dir = temp_dir("timeout");
+    write_config(&dir, &serve_nothing(), "");
+
+    let output = askme(&dir, &["--timeout", "1", "ping"]);
+    std::fs::remove_dir_all(&dir).ok();
+
+    let stderr = String::from_utf8_lossy(&output.stderr);
+    assert!(!output.status.success());
+    assert!(stderr.contains("did not answer within 1 seconds"), "{}", stderr);
+    assert!(stderr.contains("hint: raise the service's timeout"), "{}", stderr);
+}
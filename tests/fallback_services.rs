@@ -0,0 +1,51 @@
+//! The config's `fallback_services` are asked in turn until one answers with something --accept-if accepts.
+
+mod common;
+
+use common::{askme, serve_completions, temp_dir, write_config};
+
+#[test]
+fn fallback_services_are_asked_until_an_answer_is_accepted() {
+    let dir = temp_dir("fallback");
+    let (primary_url, primary) = serve_completions("I don't know");
+    let (useless_url, useless) = serve_completions("No idea");
+    let (good_url, good) = serve_completions("OK: 42");
+    let service = |name: &str, url: &str| format!("  {}:\n    class: openai\n    url: {}\n    api_key: sk-test\n    model: m\n", name, url);
+    std::fs::write(dir.join("askme.yml"), format!(
+        "default_service: primary\ndefault_prompt: basic\nsystem_prompts:\n  basic: \"Be brief.\"\nfallback_services: [down, useless, good]\nservices:\n{}{}{}{}",
+        service("primary", &primary_url),
+        // Nothing listens there
+        service("down", "http://127.0.0.1:1"),
+        service("useless", &useless_url),
+        service("good", &good_url),
+    )).unwrap();
+
+    let output = askme(&dir, &["--accept-if", "^OK", "ping"]);
+    std::fs::remove_dir_all(&dir).ok();
+
+    assert!(output.status.success(), "{}", String::from_utf8_lossy(&output.stderr));
+    assert_eq!(String::from_utf8_lossy(&output.stdout).trim(), "OK: 42");
+    let stderr = String::from_utf8_lossy(&output.stderr);
+    for name in ["down", "useless", "good"] {
+        assert!(stderr.contains(&format!("Asking fallback service '{}'", name)), "{}", stderr);
+    }
+    for requests in [primary, useless, good] {
+        assert_eq!(requests.lock().unwrap().len(), 1);
+    }
+}
+
+#[test]
+fn stream_says_it_skips_the_fallback_services() {
+    let dir = temp_dir("fallback_stream");
+    // Nothing listens there
+    write_config(&dir, "http://127.0.0.1:1", "fallback_services: [test]\n");
+
+    let output = askme(&dir, &["--stream", "ping"]);
+    let rejected = askme(&dir, &["--stream", "--cache", "ping"]);
+    std::fs::remove_dir_all(&dir).ok();
+
+    assert!(!output.status.success());
+    let stderr = String::from_utf8_lossy(&output.stderr);
+    assert!(stderr.contains("--stream doesn't try the fallback_services"), "{}", stderr);
+    assert!(String::from_utf8_lossy(&rejected.stderr).contains("cannot be used with"));
+}
@@ -0,0 +1,111 @@
+//! With `--json --json-errors-to-stderr`, stdout must hold exactly one JSON document
+//! whether the query succeeds or fails.
+
+use std::io::{BufRead, BufReader, Read, Write};
+use std::net::TcpListener;
+use std::path::PathBuf;
+use std::process::{Command, Output};
+use std::thread;
+
+/// Serves a single OpenAI-style chat completion and returns the server's base URL
+fn serve_one_completion() -> String {
+    let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+    let url = format!("http://{}", listener.local_addr().unwrap());
+
+    thread::spawn(move || {
+        let (stream, _) = listener.accept().unwrap();
+        let mut reader = BufReader::new(stream);
+        let mut content_length = 0;
+        loop {
+            let mut line = String::new();
+            reader.read_line(&mut line).unwrap();
+            if line == "\r\n" || line.is_empty() {
+                break;
+            }
+            if let Some((name, value)) = line.split_once(':')
+                && name.eq_ignore_ascii_case("content-length") {
+                    content_length = value.trim().parse().unwrap();
+            }
+        }
+        let mut body = vec![0; content_length];
+        reader.read_exact(&mut body).unwrap();
+
+        let reply = r#"{"choices":[{"message":{"content":"pong"}}]}"#;
+        let mut stream = reader.into_inner();
+        write!(stream, "HTTP/1.1 200 OK\r\nContent-Type: application/json\r\nContent-Length: {}\r\nConnection: close\r\n\r\n{}", reply.len(), reply).unwrap();
+    });
+
+    url
+}
+
+/// Writes a config with a single OpenAI service at `url` and returns its path
+fn write_config(name: &str, url: &str) -> PathBuf {
+    let path = std::env::temp_dir().join(format!("askme-test-{}-{}.yml", name, std::process::id()));
+    let config = format!(
+        "default_service: test\ndefault_prompt: basic\nsystem_prompts:\n  basic: \"Be brief.\"\nservices:\n  test:\n    class: openai\n    url: {}\n    api_key: sk-test\n    model: test-model\n",
+        url
+    );
+    std::fs::write(&path, config).unwrap();
+    path
+}
+
+fn askme(args: &[&str]) -> Output {
+    Command::new(env!("CARGO_BIN_EXE_askme"))
+        // Keep --last state out of the real cache directory
+        .env("XDG_CACHE_HOME", std::env::temp_dir())
+        .args(["--no-global-config", "--json", "--json-errors-to-stderr"])
+        .args(args)
+        .output()
+        .unwrap()
+}
+
+fn stdout_json(output: &Output) -> serde_json::Value {
+    let stdout = String::from_utf8_lossy(&output.stdout);
+    serde_json::from_str(&stdout).unwrap_or_else(|err| panic!("stdout is not a single JSON document ({}): {:?}", err, stdout))
+}
+
+#[test]
+fn success_prints_one_json_document() {
+    let config = write_config("success", &serve_one_completion());
+    let output = askme(&["-c", config.to_str().unwrap(), "ping"]);
+    std::fs::remove_file(&config).ok();
+
+    assert!(output.status.success());
+    let json = stdout_json(&output);
+    assert_eq!(json["response"], "pong");
+    assert_eq!(json["model"], "test-model");
+}
+
+#[test]
+fn request_failure_prints_json_error() {
+    // Nothing listens on the port once the listener is dropped
+    let url = {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        format!("http://{}", listener.local_addr().unwrap())
+    };
+    let config = write_config("failure", &url);
+    let output = askme(&["-c", config.to_str().unwrap(), "ping"]);
+    std::fs::remove_file(&config).ok();
+
+    assert!(!output.status.success());
+    assert!(stdout_json(&output)["error"].is_string());
+    assert!(!output.stderr.is_empty());
+}
+
+#[test]
+fn config_failure_prints_json_error() {
+    let output = askme(&["-c", "/nonexistent/askme.yml", "ping"]);
+
+    assert!(!output.status.success());
+    assert!(stdout_json(&output)["error"].is_string());
+}
+
+#[test]
+fn missing_prompt_prints_json_error() {
+    let config = write_config("noprompt", "http://127.0.0.1:9");
+    let output = askme(&["-c", config.to_str().unwrap()]);
+    std::fs::remove_file(&config).ok();
+
+    assert!(!output.status.success());
+    assert!(stdout_json(&output)["error"].is_string());
+}
@@ -0,0 +1,30 @@
+//! --last replays the saved query, even one saved before options were added, and says so
+//! clearly when the saved query can't be read.
+
+mod common;
+
+use common::{askme, serve_completions, temp_dir, write_config};
+
+#[test]
+fn last_query_replays_older_saves_and_reports_unreadable_ones() {
+    let dir = temp_dir("last_query");
+    let (url, requests) = serve_completions("pong");
+    write_config(&dir, &url, "");
+    let saved = dir.join("cache/askme/last_query.json");
+    std::fs::create_dir_all(saved.parent().unwrap()).unwrap();
+
+    // Options missing from the file take their defaults
+    let older = serde_json::json!({"input": "ping", "config": dir.join("askme.yml"), "no_global_config": true});
+    std::fs::write(&saved, older.to_string()).unwrap();
+    let replayed = askme(&dir, &["--last"]);
+    std::fs::write(&saved, "not json").unwrap();
+    let unreadable = askme(&dir, &["--last"]);
+    std::fs::remove_dir_all(&dir).ok();
+
+    assert!(replayed.status.success(), "{}", String::from_utf8_lossy(&replayed.stderr));
+    assert_eq!(String::from_utf8_lossy(&replayed.stdout).trim(), "pong");
+    assert_eq!(requests.lock().unwrap().len(), 1);
+    assert!(!unreadable.status.success());
+    let stderr = String::from_utf8_lossy(&unreadable.stderr);
+    assert!(stderr.contains("No replayable previous query"), "{}", stderr);
+}
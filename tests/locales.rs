@@ -0,0 +1,16 @@
+//! Every locale translates every key askme uses, as `--dump-locale` reports.
+
+use std::process::Command;
+
+#[test]
+fn no_locale_misses_a_key() {
+    for code in ["en", "es", "fr", "it", "de", "zh"] {
+        let output = Command::new(env!("CARGO_BIN_EXE_askme")).args(["--dump-locale", code]).output().unwrap();
+        assert!(output.status.success());
+        let missing: Vec<String> = String::from_utf8_lossy(&output.stdout).lines()
+            .filter(|line| line.ends_with("=<MISSING>"))
+            .map(String::from)
+            .collect();
+        assert!(missing.is_empty(), "{}: {:?}", code, missing);
+    }
+}
@@ -0,0 +1,37 @@
+//! --migrate-config upgrades the configuration file without changing what it means.
+
+mod common;
+
+use common::{askme, serve_completions, temp_dir};
+
+#[test]
+fn a_url_shared_by_services_moves_to_defaults() {
+    let dir = temp_dir("migrate");
+    let (url, requests) = serve_completions("pong");
+    std::fs::write(dir.join("askme.yml"), format!(
+        "default_service: fast\ndefault_prompt: basic\nsystem_prompts:\n  basic: \"Be brief.\"\nservices:\n  fast:\n    class: openai\n    url: {0}\n    api_key: sk-fast\n    model: small\n  smart:\n    class: openai\n    url: {0}\n    api_key: sk-smart\n    model: large\n  local:\n    class: ollama\n    url: http://localhost:11434\n    model: llama3\n",
+        url
+    )).unwrap();
+
+    let output = askme(&dir, &["--migrate-config"]);
+    assert!(output.status.success(), "{}", String::from_utf8_lossy(&output.stderr));
+    assert!(String::from_utf8_lossy(&output.stdout).contains("'url' shared by 2 services moved to defaults"));
+
+    let migrated: serde_yaml::Value = serde_yaml::from_str(&std::fs::read_to_string(dir.join("askme.yml")).unwrap()).unwrap();
+    assert_eq!(migrated["defaults"]["url"].as_str(), Some(url.as_str()));
+    assert!(migrated["services"]["fast"].get("url").is_none());
+    assert!(migrated["services"]["smart"].get("url").is_none());
+    assert_eq!(migrated["services"]["local"]["url"].as_str(), Some("http://localhost:11434"));
+    // The keys differ, so they stay with their services
+    assert!(migrated["defaults"].get("api_key").is_none());
+    assert_eq!(migrated["services"]["smart"]["api_key"].as_str(), Some("sk-smart"));
+
+    // The services still reach the shared url
+    for service in ["fast", "smart"] {
+        let output = askme(&dir, &["-s", service, "ping"]);
+        assert!(output.status.success(), "{}", String::from_utf8_lossy(&output.stderr));
+    }
+    std::fs::remove_dir_all(&dir).ok();
+
+    assert_eq!(requests.lock().unwrap().len(), 2);
+}
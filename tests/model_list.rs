@@ -0,0 +1,28 @@
+//! --lmodels lists a service's models, with their prices when the API publishes them.
+
+mod common;
+
+use common::{askme, serve_json, temp_dir};
+
+#[test]
+fn openrouter_models_are_listed_with_their_prices() {
+    let dir = temp_dir("lmodels");
+    let (url, _) = serve_json(serde_json::json!({"data": [
+        {"id": "vendor/large", "pricing": {"prompt": "0.000003", "completion": "0.000015"}},
+        {"id": "vendor/free", "pricing": {"prompt": "0", "completion": "0"}},
+        {"id": "vendor/unpriced"},
+    ]}));
+    std::fs::write(dir.join("askme.yml"), format!(
+        "default_service: router\ndefault_prompt: basic\nsystem_prompts:\n  basic: \"Be brief.\"\nservices:\n  router:\n    class: openrouter\n    url: {}\n    api_key: sk-test\n",
+        url
+    )).unwrap();
+
+    let output = askme(&dir, &["--lmodels", "router"]);
+    std::fs::remove_dir_all(&dir).ok();
+
+    assert!(output.status.success(), "{}", String::from_utf8_lossy(&output.stderr));
+    let stdout = String::from_utf8_lossy(&output.stdout);
+    assert!(stdout.contains("- vendor/large ($3 input / $15 output per 1M tokens)"), "{}", stdout);
+    assert!(stdout.contains("- vendor/free ($0 input / $0 output per 1M tokens)"), "{}", stdout);
+    assert!(stdout.contains("- vendor/unpriced\n"), "{}", stdout);
+}
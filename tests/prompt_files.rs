@@ -0,0 +1,36 @@
+//! A system prompt file can carry its own service, model and temperature in YAML front matter.
+
+mod common;
+
+use common::{askme, serve_completions, temp_dir};
+
+#[test]
+fn front_matter_settings_apply_under_the_command_line() {
+    let dir = temp_dir("front-matter");
+    let (default_url, default_requests) = serve_completions("from default");
+    let (reviewer_url, reviewer_requests) = serve_completions("from reviewer");
+    std::fs::write(dir.join("askme.yml"), format!(
+        "default_service: test\ndefault_prompt: basic\nsystem_prompts:\n  basic: \"Be brief.\"\nservices:\n  test:\n    class: openai\n    url: {}\n    api_key: sk-test\n    model: test-model\n  reviewer:\n    class: openai\n    url: {}\n    api_key: sk-test\n    model: review-model\n",
+        default_url, reviewer_url
+    )).unwrap();
+    let prompt = dir.join("review.md");
+    std::fs::write(&prompt, "---\nservice: reviewer\nmodel: careful-model\ntemperature: 0.2\n---\nReview this.\n").unwrap();
+    let prompt = prompt.to_str().unwrap();
+
+    let output = askme(&dir, &["--system-file", prompt, "ping"]);
+    assert!(output.status.success(), "{}", String::from_utf8_lossy(&output.stderr));
+    assert_eq!(String::from_utf8_lossy(&output.stdout).trim(), "from reviewer");
+    let output = askme(&dir, &["--system-file", prompt, "-s", "test", "-m", "cli-model", "--temperature", "0.9", "ping"]);
+    assert!(output.status.success(), "{}", String::from_utf8_lossy(&output.stderr));
+    assert_eq!(String::from_utf8_lossy(&output.stdout).trim(), "from default");
+    std::fs::remove_dir_all(&dir).ok();
+
+    let from_file = &reviewer_requests.lock().unwrap()[0];
+    assert_eq!(from_file["model"], "careful-model");
+    assert_eq!(from_file["temperature"], 0.2);
+    assert_eq!(from_file["messages"][0]["content"], "Review this.");
+    let from_cli = &default_requests.lock().unwrap()[0];
+    assert_eq!(from_cli["model"], "cli-model");
+    assert_eq!(from_cli["temperature"], 0.9);
+    assert_eq!(from_cli["messages"][0]["content"], "Review this.");
+}
@@ -0,0 +1,29 @@
+//! With --log-file, every answered query is appended to the file as one JSON line.
+
+mod common;
+
+use common::{askme, serve_completions, temp_dir, write_config};
+
+#[test]
+fn logged_queries_carry_their_tag() {
+    let dir = temp_dir("log");
+    let (url, _) = serve_completions("pong");
+    write_config(&dir, &url, "");
+    let log = dir.join("queries.jsonl");
+
+    for args in [&["--tag", "nightly", "ping"][..], &["ping"]] {
+        let output = askme(&dir, &[&["--log-file", log.to_str().unwrap()], args].concat());
+        assert!(output.status.success(), "{}", String::from_utf8_lossy(&output.stderr));
+    }
+    let entries: Vec<serde_json::Value> = std::fs::read_to_string(&log).unwrap()
+        .lines()
+        .map(|line| serde_json::from_str(line).unwrap())
+        .collect();
+    std::fs::remove_dir_all(&dir).ok();
+
+    assert_eq!(entries.len(), 2);
+    assert_eq!(entries[0]["prompt"], "ping");
+    assert_eq!(entries[0]["response"], "pong");
+    assert_eq!(entries[0]["tag"], "nightly");
+    assert!(entries[1]["tag"].is_null());
+}
@@ -0,0 +1,58 @@
+//! With `--cache`, a query is answered from the cache only when everything sent to the
+//! service is the same, sampling settings included.
+
+mod common;
+
+use common::{askme, serve_completions, temp_dir, write_config};
+
+#[test]
+fn changing_a_sampling_setting_misses_the_cache() {
+    let dir = temp_dir("cache");
+    let (url, requests) = serve_completions("pong");
+    write_config(&dir, &url, "");
+
+    let runs: &[&[&str]] = &[
+        &["--cache", "ping"],
+        // Same query: answered from the cache
+        &["--cache", "ping"],
+        &["--cache", "--max-tokens", "50", "ping"],
+        &["--cache", "--max-tokens", "500", "ping"],
+        &["--cache", "--temperature", "0.2", "ping"],
+        &["--cache", "--stop", "END", "ping"],
+    ];
+    for args in runs {
+        let output = askme(&dir, args);
+        assert!(output.status.success(), "{}", String::from_utf8_lossy(&output.stderr));
+        assert_eq!(String::from_utf8_lossy(&output.stdout).trim(), "pong");
+    }
+    std::fs::remove_dir_all(&dir).ok();
+
+    assert_eq!(requests.lock().unwrap().len(), runs.len() - 1);
+}
+
+#[test]
+fn answers_older_than_max_cache_age_are_asked_again() {
+    let dir = temp_dir("cache-age");
+    let (url, requests) = serve_completions("pong");
+    write_config(&dir, &url, "");
+
+    assert!(askme(&dir, &["--cache", "ping"]).status.success());
+    // Make the stored answer an hour old
+    let hour_ago = std::time::SystemTime::now().duration_since(std::time::UNIX_EPOCH).unwrap().as_secs() - 3600;
+    for entry in std::fs::read_dir(dir.join("cache/askme/responses")).unwrap() {
+        let path = entry.unwrap().path();
+        let mut cached: serde_json::Value = serde_json::from_str(&std::fs::read_to_string(&path).unwrap()).unwrap();
+        cached["timestamp"] = hour_ago.into();
+        std::fs::write(&path, cached.to_string()).unwrap();
+    }
+
+    let fresh_enough = askme(&dir, &["--cache", "--max-cache-age", "2h", "--verbose", "ping"]);
+    assert!(String::from_utf8_lossy(&fresh_enough.stderr).contains("stored 1h ago"));
+    assert_eq!(requests.lock().unwrap().len(), 1);
+
+    let too_old = askme(&dir, &["--cache", "--max-cache-age", "30m", "ping"]);
+    assert_eq!(String::from_utf8_lossy(&too_old.stdout).trim(), "pong");
+    std::fs::remove_dir_all(&dir).ok();
+
+    assert_eq!(requests.lock().unwrap().len(), 2);
+}
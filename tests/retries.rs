@@ -0,0 +1,33 @@
+//! Retry-After is honored in both of its forms, up to a limit past which askme gives up at once.
+
+mod common;
+
+use common::{askme, serve_status, temp_dir, write_config};
+
+/// How many requests a query made to a service answering 429 with `retry_after`, and its stderr
+fn requests_made(name: &str, retry_after: &'static str) -> (usize, String) {
+    let dir = temp_dir(name);
+    let (url, requests) = serve_status("429 Too Many Requests", retry_after);
+    write_config(&dir, &url, "    max_retries: 2\n");
+
+    let output = askme(&dir, &["ping"]);
+    assert!(!output.status.success());
+    std::fs::remove_dir_all(&dir).ok();
+    let count = requests.lock().unwrap().len();
+    (count, String::from_utf8_lossy(&output.stderr).into_owned())
+}
+
+#[test]
+fn past_http_date_retries_at_once() {
+    let (count, _) = requests_made("retries_past_date", "Retry-After: Wed, 21 Oct 2015 07:28:00 GMT\r\n");
+    assert_eq!(count, 3);
+}
+
+#[test]
+fn long_waits_are_not_retried() {
+    for (name, retry_after) in [("retries_seconds", "Retry-After: 3600\r\n"), ("retries_future_date", "Retry-After: Fri, 01 Jan 2100 00:00:00 GMT\r\n")] {
+        let (count, stderr) = requests_made(name, retry_after);
+        assert_eq!(count, 1);
+        assert!(stderr.contains("not retrying"), "{}", stderr);
+    }
+}
@@ -0,0 +1,46 @@
+//! --session conversations are kept in the user config directory, apart from caches.
+
+mod common;
+
+use common::{askme, serve_completions, temp_dir, write_config};
+
+#[test]
+fn sessions_are_stored_in_the_config_directory() {
+    let dir = temp_dir("sessions");
+    let (url, requests) = serve_completions("pong");
+    write_config(&dir, &url, "");
+
+    for _ in 0..2 {
+        let output = askme(&dir, &["--session", "work", "ping"]);
+        assert!(output.status.success(), "{}", String::from_utf8_lossy(&output.stderr));
+    }
+    let stored = dir.join("config/askme/session_work.json");
+    assert!(stored.exists());
+    assert!(!dir.join("cache/askme/session_work.json").exists());
+
+    assert!(askme(&dir, &["--clear-session", "work"]).status.success());
+    assert!(!stored.exists());
+    std::fs::remove_dir_all(&dir).ok();
+
+    // The second query carried the first exchange
+    let messages = requests.lock().unwrap()[1]["messages"].as_array().unwrap().len();
+    assert_eq!(messages, 4);
+}
+
+#[test]
+fn reasoning_is_kept_only_with_keep_thinking() {
+    let dir = temp_dir("sessions_thinking");
+    let (url, _requests) = serve_completions("<think>why</think>pong");
+    write_config(&dir, &url, "");
+
+    let stored = |session: &str| {
+        let file = dir.join(format!("config/askme/session_{}.json", session));
+        std::fs::read_to_string(file).unwrap()
+    };
+    assert!(askme(&dir, &["--session", "plain", "ping"]).status.success());
+    assert!(!stored("plain").contains("why"));
+    // -n hides the reasoning from the output but doesn't decide what is stored
+    assert!(askme(&dir, &["--session", "kept", "--keep-thinking", "-n", "ping"]).status.success());
+    assert!(stored("kept").contains("<think>\\nwhy\\n</think>"));
+    std::fs::remove_dir_all(&dir).ok();
+}
@@ -0,0 +1,43 @@
+//! --usage and --json report the tokens a query used, prompt cache hits included.
+
+mod common;
+
+use common::{askme, serve_json, temp_dir, write_config};
+
+/// What --usage prints and the `usage` of --json for a service answering `reply`
+fn usage_of(name: &str, reply: serde_json::Value) -> (String, serde_json::Value) {
+    let dir = temp_dir(name);
+    let (url, _) = serve_json(reply);
+    write_config(&dir, &url, "");
+
+    let text = askme(&dir, &["--usage", "ping"]);
+    let json = askme(&dir, &["--json", "ping"]);
+    std::fs::remove_dir_all(&dir).ok();
+
+    assert!(text.status.success(), "{}", String::from_utf8_lossy(&text.stderr));
+    let json: serde_json::Value = serde_json::from_slice(&json.stdout).unwrap();
+    (String::from_utf8_lossy(&text.stderr).into_owned(), json["usage"].clone())
+}
+
+#[test]
+fn cached_prompt_tokens_are_reported() {
+    let (stderr, usage) = usage_of("usage-cached", serde_json::json!({
+        "choices": [{"message": {"content": "pong"}, "finish_reason": "stop"}],
+        "usage": {"prompt_tokens": 100, "completion_tokens": 5, "prompt_tokens_details": {"cached_tokens": 80}},
+    }));
+    assert!(stderr.contains("Prompt cache: 80 tokens read, 0 written"), "{}", stderr);
+    assert_eq!(usage["input"], 100);
+    assert_eq!(usage["cache_read"], 80);
+    assert_eq!(usage["cache_write"], 0);
+}
+
+#[test]
+fn no_cache_line_without_cache_activity() {
+    let (stderr, usage) = usage_of("usage-uncached", serde_json::json!({
+        "choices": [{"message": {"content": "pong"}, "finish_reason": "stop"}],
+        "usage": {"prompt_tokens": 10, "completion_tokens": 5},
+    }));
+    assert!(stderr.contains("Tokens: 10 input, 5 output, 15 total"), "{}", stderr);
+    assert!(!stderr.contains("Prompt cache"), "{}", stderr);
+    assert_eq!(usage["cache_read"], 0);
+}